@@ -4,4 +4,15 @@ use clap::Parser;
 #[command(author, version)]
 pub struct CustomArgs {
     pub file: Option<std::path::PathBuf>,
+    /// recover the file from its swap file, without a file list the swap
+    /// files in the current directory and wherever swap files were written,
+    /// and the recovery copies
+    #[arg(short)]
+    pub recover: bool,
+    /// edit the file as bytes in the hex view
+    #[arg(short)]
+    pub binary: bool,
+    /// encrypt the file when writing it, asking for the key first
+    #[arg(short = 'x')]
+    pub encrypt: bool,
 }
@@ -0,0 +1,252 @@
+use anyhow::Result;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+use std::io::{Error, ErrorKind};
+
+use crate::options::{FileFormat, Options};
+
+/// byte order mark of UTF-8
+const BOM: &str = "\u{feff}";
+/// `fileencoding` of text that is not valid in any of the `fileencodings`,
+/// UTF-8 with every other byte kept as a character of [`RAW_BYTES`]
+pub const RAW: &str = "raw";
+/// private use characters standing for the bytes 0x80 to 0xff of a raw file
+const RAW_BYTES: u32 = 0xf700;
+
+/// turns the bytes of a file into buffer text with `\n` line endings,
+/// setting `fileencoding`, `fileformat` and `bomb` to what the file used
+pub fn decode(bytes: Vec<u8>, options: &mut Options) -> Result<String> {
+    if options.binary {
+        return Ok(bytes.into_iter().map(raw_char).collect());
+    }
+    let (text, encoding, bom) = decode_text(bytes, &options.fileencodings)?;
+    options.fileencoding = encoding;
+    options.bomb = bom;
+    options.fileformat = detect_format(&text, &options.fileformats, options.fileformat);
+    Ok(match options.fileformat {
+        FileFormat::Unix => text,
+        FileFormat::Dos => text.replace("\r\n", "\n"),
+        FileFormat::Mac => text.replace('\r', "\n"),
+    })
+}
+
+/// the bytes written for the buffer text, in `fileencoding` with the line
+/// ending of `fileformat` and a byte order mark with `bomb`
+pub fn encode(text: &str, options: &Options) -> Result<Vec<u8>> {
+    if options.binary {
+        return Ok(encode_raw(text));
+    }
+    let text = match options.fileformat {
+        FileFormat::Unix => std::borrow::Cow::Borrowed(text),
+        format => std::borrow::Cow::Owned(text.replace('\n', format.ending())),
+    };
+    let mut bytes = Vec::with_capacity(text.len() + BOM.len());
+    match canonical(&options.fileencoding) {
+        Some("utf-8") => {
+            if options.bomb {
+                bytes.extend_from_slice(BOM.as_bytes());
+            }
+            bytes.extend_from_slice(text.as_bytes());
+        }
+        Some(RAW) => bytes.extend(encode_raw(&text)),
+        Some(name @ ("utf-16le" | "utf-16")) => {
+            let little = name == "utf-16le";
+            let units = options.bomb.then_some(0xfeff).into_iter();
+            for unit in units.chain(text.encode_utf16()) {
+                bytes.extend(if little {
+                    unit.to_le_bytes()
+                } else {
+                    unit.to_be_bytes()
+                });
+            }
+        }
+        // ISO-8859-1 itself, encoding_rs only knows the label as windows-1252
+        Some("latin1") => {
+            for ch in text.chars() {
+                bytes.push(u8::try_from(ch).map_err(|_| conversion_failed())?);
+            }
+        }
+        Some(name) => {
+            let encoding = Encoding::for_label(name.as_bytes()).ok_or_else(unknown_encoding)?;
+            let (encoded, _, unmappable) = encoding.encode(&text);
+            if unmappable {
+                return Err(conversion_failed());
+            }
+            bytes.extend_from_slice(&encoded);
+        }
+        None => return Err(unknown_encoding()),
+    }
+    Ok(bytes)
+}
+
+/// the name an encoding is known by here, or none for an unknown one, an
+/// empty name being UTF-8 and `utf-16` big endian like in vim
+pub fn canonical(name: &str) -> Option<&str> {
+    match name.to_ascii_lowercase().as_str() {
+        "" | "utf-8" | "utf8" => Some("utf-8"),
+        "utf-16" | "ucs-2" | "utf-16be" | "ucs-2be" => Some("utf-16"),
+        "utf-16le" | "ucs-2le" => Some("utf-16le"),
+        "latin1" | "latin-1" | "iso-8859-1" => Some("latin1"),
+        "sjis" | "cp932" | "shift_jis" | "shift-jis" => Some("sjis"),
+        RAW => Some(RAW),
+        _ => Encoding::for_label(name.as_bytes()).map(|_| name),
+    }
+}
+
+/// decodes with the first of the comma separated `encodings` that fits,
+/// `ucs-bom` standing for any encoding named by a byte order mark, and
+/// keeps the bytes as they are when none does
+fn decode_text(bytes: Vec<u8>, encodings: &str) -> Result<(String, String, bool)> {
+    for name in encodings.split(',').filter(|name| !name.is_empty()) {
+        if name == "ucs-bom" {
+            if let Some((encoding, bom_length)) = Encoding::for_bom(&bytes) {
+                if let Some(text) = decode_with(encoding, &bytes[bom_length..]) {
+                    let name = match encoding {
+                        encoding if encoding == UTF_16LE => "utf-16le",
+                        encoding if encoding == UTF_16BE => "utf-16",
+                        _ => "utf-8",
+                    };
+                    return Ok((text, name.to_string(), true));
+                }
+            }
+            continue;
+        }
+        let Some(known) = canonical(name) else {
+            return Err(unknown_encoding());
+        };
+        if known == "utf-8" {
+            if let Ok(text) = std::str::from_utf8(&bytes) {
+                let (text, bom) = match text.strip_prefix(BOM) {
+                    Some(rest) => (rest, true),
+                    None => (text, false),
+                };
+                return Ok((text.to_string(), String::from("utf-8"), bom));
+            }
+            continue;
+        }
+        if known == RAW {
+            break;
+        }
+        // every byte is the character with the same number
+        if known == "latin1" {
+            let text = bytes.iter().copied().map(char::from).collect();
+            return Ok((text, known.to_string(), false));
+        }
+        let encoding = match known {
+            "utf-16" => UTF_16BE,
+            "sjis" => encoding_rs::SHIFT_JIS,
+            _ => Encoding::for_label(known.as_bytes()).ok_or_else(unknown_encoding)?,
+        };
+        if let Some(text) = decode_with(encoding, &bytes) {
+            return Ok((text, known.to_string(), false));
+        }
+    }
+    Ok((decode_raw(&bytes), String::from(RAW), false))
+}
+
+/// the text of `bytes` in `encoding` when every byte is valid and converts back
+fn decode_with(encoding: &'static Encoding, bytes: &[u8]) -> Option<String> {
+    let text = encoding
+        .decode_without_bom_handling_and_without_replacement(bytes)?
+        .into_owned();
+    // a few encodings read several byte sequences as the same character
+    if encoding != UTF_16LE && encoding != UTF_16BE && encoding.encode(&text).0 != bytes {
+        return None;
+    }
+    Some(text)
+}
+
+/// UTF-8 with every byte that is not part of a valid character, and every
+/// character that stands for a byte itself, turned into the character
+/// standing for that byte, so writing gives back the same bytes
+fn decode_raw(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    let escape = |text: &mut String, bytes: &[u8]| text.extend(bytes.iter().copied().map(raw_char));
+    for chunk in bytes.utf8_chunks() {
+        for ch in chunk.valid().chars() {
+            if is_raw_byte(ch) {
+                escape(&mut text, ch.encode_utf8(&mut [0; 4]).as_bytes());
+            } else {
+                text.push(ch);
+            }
+        }
+        escape(&mut text, chunk.invalid());
+    }
+    text
+}
+
+fn encode_raw(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    for ch in text.chars() {
+        if let Some(byte) = raw_byte(ch) {
+            bytes.push(byte);
+        } else {
+            bytes.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+    }
+    bytes
+}
+
+/// the character stands for a byte of a raw file
+pub fn is_raw_byte(ch: char) -> bool {
+    (RAW_BYTES + 0x80..=RAW_BYTES + 0xff).contains(&u32::from(ch))
+}
+
+/// the byte a character of a raw file stands for
+pub fn raw_byte(ch: char) -> Option<u8> {
+    is_raw_byte(ch).then(|| (u32::from(ch) - RAW_BYTES) as u8)
+}
+
+/// the character a byte is kept as in a raw or binary buffer
+pub fn raw_char(byte: u8) -> char {
+    if byte < 0x80 {
+        char::from(byte)
+    } else {
+        char::from_u32(RAW_BYTES + u32::from(byte)).unwrap_or(char::REPLACEMENT_CHARACTER)
+    }
+}
+
+/// a file with NUL bytes is binary, unless a byte order mark says it is
+/// UTF-16 text
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    let utf16 =
+        matches!(Encoding::for_bom(bytes), Some((encoding, _)) if encoding != encoding_rs::UTF_8);
+    bytes.contains(&0) && !utf16
+}
+
+fn unknown_encoding() -> anyhow::Error {
+    anyhow::Error::from(Error::new(
+        ErrorKind::InvalidInput,
+        "E474: unknown encoding",
+    ))
+}
+
+fn conversion_failed() -> anyhow::Error {
+    anyhow::Error::from(Error::new(
+        ErrorKind::InvalidData,
+        "E513: write error, conversion failed (make 'fenc' empty to override)",
+    ))
+}
+
+/// picks the first of the comma separated `formats` that fits every line
+/// like vim: `dos` when all lines end in `\r\n`, `unix` when there is a
+/// `\n` and `mac` when there are only `\r`, a file without line endings
+/// gets the first format and an empty list keeps `current`
+pub fn detect_format(text: &str, formats: &str, current: FileFormat) -> FileFormat {
+    let formats: Vec<FileFormat> = formats.split(',').filter_map(FileFormat::parse).collect();
+    let Some(&first) = formats.first() else {
+        return current;
+    };
+    let newlines = text.matches('\n').count();
+    let dos = text.matches("\r\n").count();
+    let returns = text.matches('\r').count();
+    let fits = |format: &FileFormat| match format {
+        FileFormat::Dos => newlines > 0 && dos == newlines,
+        FileFormat::Unix => newlines > 0,
+        FileFormat::Mac => newlines == 0 && returns > 0,
+    };
+    // a file ending in `\r\n` everywhere also fits unix, so dos is tried first
+    if formats.contains(&FileFormat::Dos) && fits(&FileFormat::Dos) {
+        return FileFormat::Dos;
+    }
+    formats.into_iter().find(fits).unwrap_or(first)
+}
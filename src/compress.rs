@@ -0,0 +1,107 @@
+use anyhow::Result;
+use std::{
+    io::{Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+/// format of a compressed file, edited as its uncompressed bytes and
+/// compressed again in memory when written, like vim's gzip plugin
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// the format whose magic bytes start `bytes`
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::Gzip)
+        } else if bytes.starts_with(b"BZh") {
+            Some(Self::Bzip2)
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+            Some(Self::Xz)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// the format named by the extension of `file`
+    pub fn from_extension(file: &Path) -> Option<Self> {
+        match file.extension()?.to_str()? {
+            "gz" => Some(Self::Gzip),
+            "bz2" => Some(Self::Bzip2),
+            "xz" => Some(Self::Xz),
+            "zst" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// the format of `file` holding `bytes`, told by the magic bytes, only a
+    /// new or empty file goes by its extension
+    pub fn detect(file: &Path, bytes: &[u8]) -> Option<Self> {
+        if bytes.is_empty() {
+            Self::from_extension(file)
+        } else {
+            Self::from_magic(bytes)
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Bzip2 => "bzip2",
+            Self::Xz => "xz",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    /// the uncompressed bytes, several concatenated streams are read one
+    /// after the other like the command line tools do
+    pub fn decompress(self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        let result = match self {
+            Self::Gzip => flate2::read::MultiGzDecoder::new(bytes).read_to_end(&mut decompressed),
+            Self::Bzip2 => bzip2::read::MultiBzDecoder::new(bytes).read_to_end(&mut decompressed),
+            Self::Xz => {
+                liblzma::read::XzDecoder::new_multi_decoder(bytes).read_to_end(&mut decompressed)
+            }
+            Self::Zstd => zstd::stream::Decoder::new(bytes)?.read_to_end(&mut decompressed),
+        };
+        result.map_err(|e| {
+            anyhow::Error::from(Error::new(
+                ErrorKind::InvalidData,
+                format!("cannot read the {} file: {e}", self.name()),
+            ))
+        })?;
+        Ok(decompressed)
+    }
+
+    /// `bytes` compressed at the default level of the format
+    pub fn compress(self, bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()?
+            }
+            Self::Bzip2 => {
+                let mut encoder =
+                    bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()?
+            }
+            Self::Xz => {
+                let mut encoder = liblzma::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(bytes)?;
+                encoder.finish()?
+            }
+            Self::Zstd => zstd::stream::encode_all(bytes, 0)?,
+        })
+    }
+}
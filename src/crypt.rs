@@ -0,0 +1,100 @@
+use anyhow::Result;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use std::io::{Error, ErrorKind};
+
+/// first bytes of an encrypted file, followed by the argon2 costs, the salt
+/// and the nonce, which are all authenticated together with the text
+const MAGIC: &[u8] = b"mini-vim crypt 1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+/// magic, memory, time and lane costs, salt and nonce
+const HEADER_LEN: usize = MAGIC.len() + 12 + SALT_LEN + NONCE_LEN;
+
+/// highest argon2 costs accepted from a file, 1 GiB of memory and 64 passes
+const MAX_MEMORY_COST: u32 = 1 << 20;
+const MAX_TIME_COST: u32 = 64;
+
+/// the file starts like one written by [`encrypt`]
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// `plain` encrypted with XChaCha20-Poly1305 under a key derived from `key`
+/// with argon2id, with a new salt and nonce for every write
+pub fn encrypt(plain: &[u8], key: &str) -> Result<Vec<u8>> {
+    let params = Params::default();
+    let mut salt = [0; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut header = Vec::with_capacity(HEADER_LEN + plain.len() + 16);
+    header.extend_from_slice(MAGIC);
+    for cost in [params.m_cost(), params.t_cost(), params.p_cost()] {
+        header.extend_from_slice(&cost.to_le_bytes());
+    }
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&nonce);
+    let cipher = cipher(key, &salt, params)?;
+    let sealed = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plain,
+                aad: &header,
+            },
+        )
+        .map_err(|_| anyhow::Error::from(Error::other("E1199: Cannot encrypt the file")))?;
+    header.extend(sealed);
+    Ok(header)
+}
+
+/// the text of a file written by [`encrypt`], failing for a wrong key or
+/// a file changed since it was written
+pub fn decrypt(bytes: &[u8], key: &str) -> Result<Vec<u8>> {
+    let failed = || {
+        anyhow::Error::from(Error::new(
+            ErrorKind::InvalidData,
+            "E1200: Decryption failed, wrong key or damaged file",
+        ))
+    };
+    if !is_encrypted(bytes) || bytes.len() < HEADER_LEN {
+        return Err(failed());
+    }
+    let (header, sealed) = bytes.split_at(HEADER_LEN);
+    let cost = |index: usize| {
+        let start = MAGIC.len() + 4 * index;
+        u32::from_le_bytes([
+            header[start],
+            header[start + 1],
+            header[start + 2],
+            header[start + 3],
+        ])
+    };
+    // a damaged or hostile header must not make opening the file take forever
+    if cost(0) > MAX_MEMORY_COST || cost(1) > MAX_TIME_COST {
+        return Err(failed());
+    }
+    let params = Params::new(cost(0), cost(1), cost(2), None).map_err(|_| failed())?;
+    let salt = &header[MAGIC.len() + 12..MAGIC.len() + 12 + SALT_LEN];
+    let nonce = XNonce::from_slice(&header[HEADER_LEN - NONCE_LEN..]);
+    cipher(key, salt, params)?
+        .decrypt(
+            nonce,
+            Payload {
+                msg: sealed,
+                aad: header,
+            },
+        )
+        .map_err(|_| failed())
+}
+
+fn cipher(key: &str, salt: &[u8], params: Params) -> Result<XChaCha20Poly1305> {
+    let mut derived = [0; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(key.as_bytes(), salt, &mut derived)
+        .map_err(|e| anyhow::Error::from(Error::other(format!("cannot derive the key: {e}"))))?;
+    Ok(XChaCha20Poly1305::new(&derived.into()))
+}
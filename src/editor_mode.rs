@@ -1,15 +1,53 @@
 use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent};
 use std::{
     io::{Error, ErrorKind},
     path::PathBuf,
 };
 
-use crate::{args::CustomArgs, editor_state::State};
+use crate::{
+    args::CustomArgs,
+    editor_state::State,
+    keys::from_notation,
+    options::Options,
+    tui::{execute_keys, handle_key},
+};
 
 pub enum EditorMode {
     Command(String),
     Edit(Option<PathBuf>),
     Idle(Option<String>),
+    /// typing the key of an encrypted file on the command line
+    Key(KeyPrompt),
+    Replace(Option<PathBuf>),
+    Visual(Selection),
+}
+
+/// start of a visual selection, the cursor is the other end
+#[derive(Clone, Copy)]
+pub struct Selection {
+    pub anchor: usize,
+    pub linewise: bool,
+}
+
+/// a key typed on the command line, shown masked
+pub struct KeyPrompt {
+    pub typed: String,
+    /// the file was opened encrypted and waits for its key, otherwise a new
+    /// key for writing it is typed
+    pub unlock: bool,
+    /// the new key typed first, while it is typed again to confirm it
+    pub first: Option<String>,
+}
+
+impl KeyPrompt {
+    pub fn message(&self) -> &'static str {
+        if self.first.is_some() {
+            "Enter same key again: "
+        } else {
+            "Enter encryption key: "
+        }
+    }
 }
 
 impl EditorMode {
@@ -25,6 +63,20 @@ impl EditorMode {
         *self = Self::Command(String::new());
     }
 
+    /// command mode started from visual mode, operating on the selected lines
+    pub fn enter_command_mode_on_selection(&mut self) {
+        *self = Self::Command(String::from("'<,'>"));
+    }
+
+    /// asks for the key of the file, `unlock` decrypting the opened file
+    pub fn enter_key_mode(&mut self, unlock: bool) {
+        *self = Self::Key(KeyPrompt {
+            typed: String::new(),
+            unlock,
+            first: None,
+        });
+    }
+
     pub fn enter_idle_mode(&mut self, message: Option<String>) {
         *self = Self::Idle(message);
     }
@@ -33,41 +85,164 @@ impl EditorMode {
         *self = Self::Edit(file);
     }
 
+    pub fn enter_replace_mode(&mut self, file: Option<PathBuf>) {
+        *self = Self::Replace(file);
+    }
+
+    pub fn enter_visual_mode(&mut self, anchor: usize, linewise: bool) {
+        *self = Self::Visual(Selection { anchor, linewise });
+    }
+
     pub fn update_command(&mut self, ch: char) {
-        if let Self::Command(c) = self {
+        if let Self::Command(c) | Self::Key(KeyPrompt { typed: c, .. }) = self {
             c.push(ch);
         }
     }
 
     pub fn remove_from_command(&mut self) {
-        if let Self::Command(c) = self {
+        if let Self::Command(c) | Self::Key(KeyPrompt { typed: c, .. }) = self {
             c.pop();
         }
     }
 
+    /// takes the typed key on `Enter`, returning the message to show or
+    /// none while the new key still has to be typed again
+    pub fn apply_key(&mut self, state: &mut State) -> Result<Option<String>> {
+        let Self::Key(prompt) = self else {
+            return Ok(None);
+        };
+        let typed = std::mem::take(&mut prompt.typed);
+        let name = state.file_name().unwrap_or("[No Name]").to_string();
+        if prompt.unlock {
+            state.unlock(typed)?;
+            return Ok(Some(format!("\"{name}\" decrypted")));
+        }
+        let Some(first) = prompt.first.take() else {
+            prompt.first = Some(typed);
+            return Ok(None);
+        };
+        if first != typed {
+            return Err(anyhow::Error::from(Error::new(
+                ErrorKind::InvalidInput,
+                "Keys don't match!",
+            )));
+        }
+        state.set_key(typed);
+        Ok(Some(match state.key {
+            Some(_) => format!("\"{name}\" is encrypted when written"),
+            None => format!("\"{name}\" is written unencrypted"),
+        }))
+    }
+
+    /// byte range covered by the visual selection
+    pub fn selection(&self, state: &State) -> Option<(usize, usize)> {
+        if let Self::Visual(selection) = self {
+            let start = selection.anchor.min(state.cursor.location);
+            let end = selection.anchor.max(state.cursor.location);
+            if selection.linewise {
+                Some((
+                    state.line_start(state.line_of(start)),
+                    state.line_end(state.line_of(end)),
+                ))
+            } else {
+                let last = state.content[end..].chars().next();
+                Some((start, end + last.map_or(0, char::len_utf8)))
+            }
+        } else {
+            None
+        }
+    }
+
     pub fn apply_command(&mut self, state: &mut State) -> Result<String> {
         if let EditorMode::Command(c) = self {
-            if c == "q" || c == "quit" {
+            if !c.is_empty() {
+                state.last_command = Some(c.clone());
+            }
+            // a search of a large file takes everything after the `/` as its
+            // pattern, an empty one searches for the last pattern again
+            if let Some(pattern) = c.strip_prefix('/').filter(|_| state.large.is_some()) {
+                if !pattern.is_empty() {
+                    state.last_search = Some(pattern.to_string());
+                }
+                return state.search_next();
+            }
+            let (range, c) = parse_range(c, state)?;
+            if let Some(rest) = strip_command(c, "q", "quit") {
+                if !parse_bang(rest)? && state.is_modified() {
+                    return Err(no_write_since_last_change());
+                }
                 state.end_program();
                 Ok(String::from("exiting mini-vim"))
-            } else if c == "w" || c == "write" {
-                match state.flush_file() {
+            } else if let Some(rest) = strip_command(c, "cq", "cquit") {
+                // quits without writing, reporting failure to whoever started the editor
+                let rest = rest.strip_prefix('!').unwrap_or(rest).trim();
+                state.exit_code = if rest.is_empty() {
+                    1
+                } else {
+                    rest.parse().map_err(|_| trailing_characters())?
+                };
+                state.end_program();
+                Ok(String::from("exiting mini-vim"))
+            } else if let Some(rest) = strip_command(c, "w", "write") {
+                match state.write_buffer(parse_bang(rest)?) {
                     Ok(_) => Ok(String::from("File written successfully")),
-                    Err(e) => Err(anyhow::Error::from(Error::new(
-                        ErrorKind::Other,
-                        e.to_string(),
-                    ))),
+                    Err(e) => Err(anyhow::Error::from(Error::other(e.to_string()))),
                 }
-            } else if c == "wq" {
-                match state.flush_file() {
+            } else if let Some(rest) = strip_command(c, "wq", "wq") {
+                match state.write_buffer(parse_bang(rest)?) {
                     Ok(_) => Ok(String::from("File written successfully")),
-                    Err(e) => Err(anyhow::Error::from(Error::new(
-                        ErrorKind::Other,
-                        e.to_string(),
-                    ))),
+                    Err(e) => Err(anyhow::Error::from(Error::other(e.to_string()))),
                 }?;
                 state.end_program();
                 Ok(String::from("exiting mini-vim"))
+            } else if let Some(rest) =
+                strip_command(c, "x", "xit").or_else(|| strip_command(c, "exi", "exit"))
+            {
+                state.write_and_quit(parse_bang(rest)?)?;
+                Ok(String::from("exiting mini-vim"))
+            } else if let Some(rest) =
+                strip_command(c, "sus", "suspend").or_else(|| strip_command(c, "st", "stop"))
+            {
+                // a bang would skip writing the file with `autowrite`, which is not there
+                parse_bang(rest)?;
+                state.suspend_requested = true;
+                Ok(String::new())
+            } else if let Some(rest) = strip_command(c, "X", "X") {
+                if !rest.trim().is_empty() {
+                    return Err(trailing_characters());
+                }
+                // a file opened without its key gets it, otherwise a new key is set
+                let unlock = state.locked.is_some();
+                self.enter_key_mode(unlock);
+                Ok(String::new())
+            } else if let Some(arguments) = strip_command(c, "se", "set") {
+                // how the file is stored is part of the buffer, like its text
+                let stored = |options: &Options| {
+                    (
+                        options.fileformat,
+                        options.bomb,
+                        options.fileencoding.clone(),
+                    )
+                };
+                let before = stored(&state.options);
+                let binary = state.options.binary;
+                let result = state.options.set(arguments);
+                if before != stored(&state.options) {
+                    state.changes += 1;
+                }
+                // the buffer is converted between bytes and text, which the option can not do
+                if state.options.binary != binary {
+                    let wanted = std::mem::replace(&mut state.options.binary, binary);
+                    state.set_binary(wanted)?;
+                }
+                result
+            } else if let Some(keys) = strip_command(c, "norm", "normal") {
+                // a bang skips mappings in vim, there are none to skip yet
+                let keys = keys.strip_prefix('!').unwrap_or(keys).trim_start();
+                let current = state.cursor_line();
+                let (first, last) = range.unwrap_or((current, current));
+                execute_normal(state, first, last, keys)?;
+                Ok(String::new())
             } else {
                 Err(anyhow::Error::from(Error::new(
                     ErrorKind::InvalidData,
@@ -82,13 +257,18 @@ impl EditorMode {
         }
     }
 
-    pub fn get_file_name(&self) -> Option<&str> {
-        if let Self::Edit(Some(file)) = self {
-            Some(file.file_name().expect("Invalid file").to_str().unwrap())
-        } else {
-            None
+    /// name of the mode for the status line
+    pub fn mode_name(&self) -> &'static str {
+        match self {
+            Self::Command(_) | Self::Key(_) => "Command",
+            Self::Edit(_) => "Edit",
+            Self::Replace(_) => "Replace",
+            Self::Idle(_) => "Idle",
+            Self::Visual(selection) if selection.linewise => "Visual Line",
+            Self::Visual(_) => "Visual",
         }
     }
+
     pub fn display_mode(&self) -> String {
         match self {
             Self::Command(c) => {
@@ -96,7 +276,15 @@ impl EditorMode {
                 display.push_str(c);
                 display
             }
+            Self::Key(prompt) => {
+                format!(
+                    "{}{}",
+                    prompt.message(),
+                    "*".repeat(prompt.typed.chars().count())
+                )
+            }
             Self::Edit(_) => String::from("Edit"),
+            Self::Replace(_) => String::from("Replace"),
             Self::Idle(message) => {
                 if let Some(message) = message {
                     message.to_owned()
@@ -104,6 +292,131 @@ impl EditorMode {
                     String::from("Idle")
                 }
             }
+            Self::Visual(selection) => {
+                if selection.linewise {
+                    String::from("Visual Line")
+                } else {
+                    String::from("Visual")
+                }
+            }
+        }
+    }
+}
+
+/// whether the rest of a command is a `!`, anything else is an error
+fn parse_bang(rest: &str) -> Result<bool> {
+    match rest.trim_end() {
+        "" => Ok(false),
+        "!" => Ok(true),
+        _ => Err(trailing_characters()),
+    }
+}
+
+fn trailing_characters() -> anyhow::Error {
+    anyhow::Error::from(Error::new(ErrorKind::InvalidInput, "trailing characters"))
+}
+
+fn no_write_since_last_change() -> anyhow::Error {
+    anyhow::Error::from(Error::other(
+        "E37: No write since last change (add ! to override)",
+    ))
+}
+
+/// returns the rest of the command if it starts with a name between
+/// the shortest abbreviation `short` and the full name `long`
+fn strip_command<'a>(command: &'a str, short: &str, long: &str) -> Option<&'a str> {
+    let name_len = command
+        .find(|ch: char| !ch.is_ascii_alphabetic())
+        .unwrap_or(command.len());
+    let name = &command[..name_len];
+    if name.len() >= short.len() && long.starts_with(name) {
+        Some(&command[name_len..])
+    } else {
+        None
+    }
+}
+
+/// splits a leading line range like `%`, `3,$` or `'<,'>` from the command,
+/// lines are zero based and the range is inclusive
+fn parse_range<'a>(command: &'a str, state: &State) -> Result<(Option<(usize, usize)>, &'a str)> {
+    if let Some(rest) = command.strip_prefix('%') {
+        return Ok((Some((0, state.line_count() - 1)), rest));
+    }
+    let (first, rest) = parse_address(command, state)?;
+    let Some(first) = first else {
+        return Ok((None, command));
+    };
+    if let Some(rest) = rest.strip_prefix(',') {
+        let (last, rest) = parse_address(rest, state)?;
+        let last = last.unwrap_or(first);
+        if last < first {
+            return Err(anyhow::Error::from(Error::new(
+                ErrorKind::InvalidInput,
+                "backwards range given",
+            )));
+        }
+        Ok((Some((first, last)), rest))
+    } else {
+        Ok((Some((first, first)), rest))
+    }
+}
+
+fn parse_address<'a>(command: &'a str, state: &State) -> Result<(Option<usize>, &'a str)> {
+    let last_line = state.line_count() - 1;
+    if let Some(rest) = command.strip_prefix('.') {
+        Ok((Some(state.cursor_line()), rest))
+    } else if let Some(rest) = command.strip_prefix('$') {
+        Ok((Some(last_line), rest))
+    } else if let Some(rest) = command.strip_prefix('\'') {
+        let (start, end) = state
+            .visual_marks
+            .ok_or_else(|| anyhow::Error::from(Error::new(ErrorKind::NotFound, "mark not set")))?;
+        if let Some(rest) = rest.strip_prefix('<') {
+            Ok((Some(state.line_of(start)), rest))
+        } else if let Some(rest) = rest.strip_prefix('>') {
+            Ok((Some(state.line_of(end)), rest))
+        } else {
+            Err(anyhow::Error::from(Error::new(
+                ErrorKind::InvalidInput,
+                "invalid mark",
+            )))
+        }
+    } else {
+        let digits = command
+            .find(|ch: char| !ch.is_ascii_digit())
+            .unwrap_or(command.len());
+        if digits == 0 {
+            return Ok((None, command));
+        }
+        let line: usize = command[..digits].parse()?;
+        Ok((
+            Some(line.saturating_sub(1).min(last_line)),
+            &command[digits..],
+        ))
+    }
+}
+
+/// runs `keys` in idle mode once for every line of the range,
+/// special keys are written like `<Esc>` or `<CR>`
+fn execute_normal(state: &mut State, first: usize, mut last: usize, keys: &str) -> Result<()> {
+    let keys = from_notation(keys);
+    let mut line = first;
+    while line <= last && line < state.line_count() {
+        let lines_before = state.line_count() as isize;
+        state.move_to_line(line);
+        let mut mode = EditorMode::Idle(None);
+        execute_keys(state, &mut mode, &keys)?;
+        // an unfinished command is abandoned, like vim ending with <Esc>
+        if let EditorMode::Idle(_) = mode {
+            state.stacked_command = None;
+            state.count = None;
+            state.discard_change();
+        } else {
+            handle_key(state, &mut mode, KeyEvent::from(KeyCode::Esc))?;
         }
+        let shift = state.line_count() as isize - lines_before;
+        last = (last as isize + shift).max(0) as usize;
+        line = (line as isize + 1 + shift).max(0) as usize;
     }
+    Ok(())
 }
@@ -1,83 +1,475 @@
 use anyhow::Result;
 use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
     fs::{self, File},
-    io::{Error, ErrorKind},
-    path::PathBuf,
+    hash::{Hash, Hasher},
+    io::{Error, ErrorKind, Read},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
 };
 
-use crate::cursor::Cursor;
+use crossterm::event::KeyEvent;
+
+use crate::{
+    codec,
+    compress::Compression,
+    crypt,
+    cursor::Cursor,
+    large::{self, LargeFile},
+    options::Options,
+    signs::Signs,
+    swap::{self, SwapChoice, SwapInfo},
+    viewport::Viewport,
+    wrap,
+    writer::write_file,
+};
+
+/// a complete change kept as the keys that made it, replayed by `.`
+#[derive(Clone, Default)]
+pub struct Change {
+    pub count: Option<usize>,
+    pub keys: Vec<KeyEvent>,
+}
+
+/// what the file on disk looked like when it was last read or written here
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileStamp {
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+    /// hash of the bytes, telling a touched file from a changed one
+    pub hash: u64,
+}
+
+impl FileStamp {
+    /// stamp of `file` holding `bytes`
+    pub fn of(file: &Path, bytes: &[u8]) -> Option<Self> {
+        let meta = fs::metadata(file).ok()?;
+        Some(Self {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+            hash: hash_bytes(bytes),
+        })
+    }
+}
+
+/// text of a register, macros are kept here in key notation
+#[derive(Clone, Default)]
+pub struct Register {
+    pub text: String,
+    pub linewise: bool,
+}
 
 pub struct State {
     pub running: bool,
     pub file: Option<PathBuf>,
+    /// absolute path of the file for `%F`, resolved when it is opened or
+    /// written instead of on every redraw
+    pub full_path: Option<PathBuf>,
     pub content: String,
     pub cursor: Cursor,
     pub stacked_command: Option<String>,
+    /// byte offsets of the last visual selection, used by the `'<,'>` range
+    pub visual_marks: Option<(usize, usize)>,
+    /// count typed before a command in idle mode
+    pub count: Option<usize>,
+    pub last_change: Option<Change>,
+    pub recording_change: Option<Change>,
+    /// keys waiting to be handled, macros are played by pushing here
+    pub typeahead: VecDeque<KeyEvent>,
+    pub registers: HashMap<char, Register>,
+    /// register named with `"` for the next yank, delete or put
+    pub pending_register: Option<char>,
+    /// register and keys of the macro being recorded with `q`
+    pub recording_macro: Option<(char, Vec<KeyEvent>)>,
+    /// register used by the last `@`, repeated by `@@`
+    pub last_macro: Option<char>,
+    /// last executed command line, repeated by `@:`
+    pub last_command: Option<String>,
+    pub options: Options,
+    pub viewport: Viewport,
+    pub signs: Signs,
+    /// generation counter bumped by every edit
+    pub changes: usize,
+    /// value of `changes` when the file was last written
+    pub saved_changes: usize,
+    /// the file on disk when it was last read or written, none while it did not exist
+    pub file_stamp: Option<FileStamp>,
+    /// time and offset of the last mouse click, to notice double clicks
+    pub last_click: Option<(Instant, usize)>,
+    /// `Ctrl-Z` or `:suspend` asked the main loop to stop the program
+    pub suspend_requested: bool,
+    /// status the program exits with, set by `:cq`
+    pub exit_code: i32,
+    /// swap file keeping unsaved changes, none when opened read-only
+    pub swap: Option<PathBuf>,
+    /// value of `changes` when the swap file was last written
+    pub swapped_changes: Option<usize>,
+    /// the cursor is on the low nibble of its byte in the hex view
+    pub hex_nibble: bool,
+    /// characters typed over in the current replace session, none where a
+    /// character was added, put back one by one with backspace
+    pub replaced: Vec<Option<char>>,
+    /// the file mapped instead of read when it is above `largefile`, the
+    /// content stays empty then
+    pub large: Option<LargeFile>,
+    /// pattern of the last `/` search, repeated by `n`
+    pub last_search: Option<String>,
+    /// format the file is compressed in, it is compressed the same way when written
+    pub compression: Option<Compression>,
+    /// key the file is encrypted with when written, set with `:X`
+    pub key: Option<String>,
+    /// an encrypted file opened without its key, the content stays empty
+    /// until the key is given
+    pub locked: Option<Vec<u8>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            running: true,
+            file: None,
+            full_path: None,
+            content: String::new(),
+            cursor: Cursor::new(),
+            stacked_command: None,
+            visual_marks: None,
+            count: None,
+            last_change: None,
+            recording_change: None,
+            typeahead: VecDeque::new(),
+            registers: HashMap::new(),
+            pending_register: None,
+            recording_macro: None,
+            last_macro: None,
+            last_command: None,
+            options: Options::default(),
+            viewport: Viewport::default(),
+            signs: Signs::default(),
+            changes: 0,
+            saved_changes: 0,
+            file_stamp: None,
+            last_click: None,
+            suspend_requested: false,
+            exit_code: 0,
+            swap: None,
+            swapped_changes: None,
+            hex_nibble: false,
+            replaced: Vec::new(),
+            large: None,
+            last_search: None,
+            compression: None,
+            key: None,
+            locked: None,
+        }
+    }
 }
 
 impl State {
+    /// opens `file`, asking on the terminal what to do when a swap file is found
     pub fn begin_from_file(file: Option<PathBuf>) -> Result<Self> {
+        Self::open_with_swap(file, swap::ask)
+    }
+
+    /// opens `file`, letting `ask` decide about an existing swap file
+    pub fn open_with_swap(
+        file: Option<PathBuf>,
+        ask: impl FnOnce(&SwapInfo) -> Result<SwapChoice>,
+    ) -> Result<Self> {
+        let mut state = Self::read_file(file)?;
+        swap::attach_swap(&mut state, ask)?;
+        Ok(state)
+    }
+
+    fn read_file(file: Option<PathBuf>) -> Result<Self> {
+        let mut state = Self::open_file(file)?;
+        state.resolve_path();
+        Ok(state)
+    }
+
+    fn open_file(file: Option<PathBuf>) -> Result<Self> {
         if let Some(file) = file {
             let open_file = File::open(file.clone());
             match open_file {
-                Ok(_) => Ok(Self {
-                    running: true,
-                    file: Some(file.clone()),
-                    content: fs::read_to_string(file)?,
-                    cursor: Cursor::new(),
-                    stacked_command: None,
-                }),
+                Ok(_) => {
+                    let mut state = Self {
+                        file: Some(file.clone()),
+                        ..Self::default()
+                    };
+                    state.load()?;
+                    Ok(state)
+                }
                 Err(e) => {
                     if e.kind() == ErrorKind::NotFound {
                         // new file
                         Ok(Self {
-                            running: true,
+                            compression: Compression::from_extension(&file),
                             file: Some(file.clone()),
-                            content: String::new(),
-                            cursor: Cursor::new(),
-                            stacked_command: None,
+                            ..Self::default()
                         })
                     } else {
-                        Ok(Self {
-                            running: true,
-                            file: None,
-                            content: String::new(),
-                            cursor: Cursor::new(),
-                            stacked_command: None,
-                        })
+                        Ok(Self::default())
                     }
                 }
             }
         } else {
             // start in a buffer
-            Ok(Self {
-                running: true,
-                file: None,
-                content: String::new(),
-                cursor: Cursor::new(),
-                stacked_command: None,
+            Ok(Self::default())
+        }
+    }
+
+    /// remembers the absolute path of the file, a file that does not exist
+    /// yet keeps the path it was given until it is written
+    fn resolve_path(&mut self) {
+        self.full_path = self
+            .file
+            .as_ref()
+            .map(|file| fs::canonicalize(file).unwrap_or_else(|_| file.clone()));
+    }
+
+    /// the file was written, created or removed by another program since it
+    /// was read or written here, a file only touched counts as unchanged
+    pub fn changed_on_disk(&self) -> bool {
+        let Some(file) = &self.file else {
+            return false;
+        };
+        let meta = fs::metadata(file).ok();
+        match (meta, &self.file_stamp) {
+            (None, None) => false,
+            (Some(meta), Some(stamp)) => {
+                if meta.modified().ok() == stamp.modified && meta.len() == stamp.len {
+                    return false;
+                }
+                // a large file is not read again only to compare it
+                meta.len() != stamp.len
+                    || self.large.is_some()
+                    || fs::read(file).map_or(true, |bytes| hash_bytes(&bytes) != stamp.hash)
+            }
+            _ => true,
+        }
+    }
+
+    /// reads the file again, dropping the text in the buffer
+    pub fn reload(&mut self) -> Result<()> {
+        let position = self.large.as_ref().map(|large| (large.cursor, large.top));
+        self.load()?;
+        self.changes += 1;
+        self.saved_changes = self.changes;
+        if let (Some(large), Some((cursor, top))) = (&mut self.large, position) {
+            large.cursor = large.line_start(cursor);
+            large.top = large.line_start(top);
+        }
+        self.cursor.location = self.cursor.location.min(self.content.len());
+        // the cursor may now be inside a character
+        while !self.content.is_char_boundary(self.cursor.location) {
+            self.cursor.location -= 1;
+        }
+        Ok(())
+    }
+
+    /// reads the file into the buffer, or maps it when it is above
+    /// `largefile` and shows it read-only
+    fn load(&mut self) -> Result<()> {
+        let Some(file) = self.file.clone() else {
+            return Ok(());
+        };
+        let mut head = Vec::new();
+        File::open(&file)?.take(16).read_to_end(&mut head)?;
+        // a compressed or encrypted file is only of use decoded, which a map can not show
+        let plain = Compression::from_magic(&head).is_none() && !crypt::is_encrypted(&head);
+        if plain && large::is_large(fs::metadata(&file)?.len(), self.options.largefile) {
+            self.large = Some(LargeFile::open(&file)?);
+            self.file_stamp = FileStamp::of(&file, &[]);
+            self.content.clear();
+            self.cursor.location = 0;
+            self.options.readonly = true;
+            return Ok(());
+        }
+        self.large = None;
+        let bytes = fs::read(&file)?;
+        // the stamp is of the file on disk, which is what changes underneath
+        self.file_stamp = FileStamp::of(&file, &bytes);
+        if !crypt::is_encrypted(&bytes) {
+            return self.unpack(&file, bytes);
+        }
+        match &self.key {
+            Some(key) => {
+                let bytes = crypt::decrypt(&bytes, key)?;
+                self.unpack(&file, bytes)
+            }
+            // the text waits for the key, which is asked for on the command line
+            None => {
+                self.locked = Some(bytes);
+                self.content.clear();
+                self.cursor.location = 0;
+                Ok(())
+            }
+        }
+    }
+
+    /// turns the decrypted bytes of `file` into the buffer text, setting the
+    /// options describing how the text is stored like `fileformat`
+    fn unpack(&mut self, file: &Path, bytes: Vec<u8>) -> Result<()> {
+        self.compression = Compression::detect(file, &bytes);
+        let bytes = match self.compression {
+            Some(compression) => compression.decompress(&bytes)?,
+            None => bytes,
+        };
+        self.options.binary |= codec::looks_binary(&bytes);
+        self.content = codec::decode(bytes, &mut self.options)?;
+        Ok(())
+    }
+
+    /// decrypts the file opened without its key, which is kept for writing
+    pub fn unlock(&mut self, key: String) -> Result<()> {
+        let (Some(file), Some(bytes)) = (self.file.clone(), &self.locked) else {
+            return Ok(());
+        };
+        let bytes = crypt::decrypt(bytes, &key)?;
+        self.unpack(&file, bytes)?;
+        self.locked = None;
+        self.key = Some(key);
+        Ok(())
+    }
+
+    /// sets the key the file is encrypted with when written, an empty key
+    /// writes it unencrypted again
+    pub fn set_key(&mut self, key: String) {
+        self.key = (!key.is_empty()).then_some(key);
+        self.changes += 1;
+        // the swap file would hold the text unencrypted
+        swap::remove_swap(self);
+    }
+
+    /// a large file can not be changed, whatever options were set
+    pub fn is_modified(&self) -> bool {
+        self.large.is_none() && self.changes != self.saved_changes
+    }
+
+    /// moves to the next match of the last search after the cursor of a
+    /// large file, continuing at the top, returning the message to show
+    pub fn search_next(&mut self) -> Result<String> {
+        let Some(pattern) = self.last_search.clone() else {
+            return Err(anyhow::Error::from(Error::new(
+                ErrorKind::NotFound,
+                "E35: No previous regular expression",
+            )));
+        };
+        let found = self.large.as_mut().and_then(|large| {
+            large.find(&pattern, large.cursor).map(|(at, wrapped)| {
+                large.cursor = at;
+                wrapped
             })
+        });
+        match found {
+            Some(true) => Ok(String::from("search hit BOTTOM, continuing at TOP")),
+            Some(false) => Ok(format!("/{pattern}")),
+            None => Err(anyhow::Error::from(Error::new(
+                ErrorKind::NotFound,
+                format!("E486: Pattern not found: {pattern}"),
+            ))),
         }
     }
+
+    /// file type guessed from the extension of the file
+    pub fn file_type(&self) -> Option<&'static str> {
+        let extension = self.file.as_ref()?.extension()?.to_str()?;
+        Some(match extension {
+            "rs" => "rust",
+            "py" => "python",
+            "md" => "markdown",
+            "toml" => "toml",
+            "json" => "json",
+            "yaml" | "yml" => "yaml",
+            "c" | "h" => "c",
+            "cpp" | "cc" | "hpp" => "cpp",
+            "js" => "javascript",
+            "ts" => "typescript",
+            "go" => "go",
+            "sh" => "sh",
+            "html" => "html",
+            "css" => "css",
+            "txt" => "text",
+            _ => return None,
+        })
+    }
+
+    /// encoding used to write the file
+    pub fn file_encoding(&self) -> &str {
+        &self.options.fileencoding
+    }
+
+    /// line ending written after each line
+    pub fn file_format(&self) -> &'static str {
+        self.options.fileformat.name()
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.file.as_ref()?.file_name()?.to_str()
+    }
+
+    /// inserts pasted text as a whole
+    pub fn insert_text(&mut self, text: &str) {
+        self.changes += 1;
+        self.content.insert_str(self.cursor.location, text);
+        self.cursor.move_ahead(text.len());
+    }
+
     pub fn update_edit(&mut self, ch: char) {
+        self.changes += 1;
         self.content.insert(self.cursor.location, ch);
-        self.cursor.move_char();
+        self.cursor.move_ahead(ch.len_utf8());
+    }
+
+    /// overwrites the character under the cursor, at the end of a line it inserts
+    pub fn replace_edit(&mut self, ch: char) {
+        self.changes += 1;
+        let old = self.content[self.cursor.location..]
+            .chars()
+            .next()
+            .filter(|old| *old != '\n');
+        if old.is_some() {
+            self.content.remove(self.cursor.location);
+        }
+        self.replaced.push(old);
+        self.content.insert(self.cursor.location, ch);
+        self.cursor.move_ahead(ch.len_utf8());
+    }
+
+    /// backspace in replace mode puts back what the last typed character
+    /// replaced, before the start of the session it only moves the cursor
+    pub fn unreplace_edit(&mut self) {
+        let Some(old) = self.replaced.pop() else {
+            self.move_cursor_behind();
+            return;
+        };
+        let end = self.cursor.location;
+        let Some(typed) = self.content[..end].chars().next_back() else {
+            return;
+        };
+        self.changes += 1;
+        let start = end - typed.len_utf8();
+        let old = old.map(String::from).unwrap_or_default();
+        self.content.replace_range(start..end, &old);
+        self.cursor.location = start;
     }
 
     pub fn add_newline_edit(&mut self) {
+        self.changes += 1;
         self.content.insert(self.cursor.location, '\n');
         self.cursor.move_char();
     }
 
     pub fn remove_from_edit(&mut self) {
-        if self.cursor.location == 0 {
+        let Some(ch) = self.content[..self.cursor.location].chars().next_back() else {
             return;
-        }
-        self.content.remove(self.cursor.location - 1);
-        self.cursor.back_char();
+        };
+        self.changes += 1;
+        self.cursor.move_behind(ch.len_utf8());
+        self.content.remove(self.cursor.location);
     }
 
     pub fn delete_line(&mut self) {
+        self.changes += 1;
         if let Some(before) = self.content.get(..self.cursor.location) {
             let prev_newline = before.rfind('\n').unwrap_or(0);
             if let Some(after) = self.content.get(self.cursor.location..) {
@@ -108,6 +500,7 @@ impl State {
     }
 
     pub fn next_line_insert(&mut self) {
+        self.changes += 1;
         if self.content.is_empty() {
             self.content.push('\n');
             self.cursor.location = 1;
@@ -127,6 +520,7 @@ impl State {
     }
 
     pub fn above_line_insert(&mut self) {
+        self.changes += 1;
         if self.content.is_empty() {
             self.content.push('\n');
             return;
@@ -137,7 +531,7 @@ impl State {
                 self.add_newline_edit();
             } else {
                 self.cursor.location = 0;
-                self.content.push('\n');
+                self.content.insert(0, '\n');
             }
         } else {
             self.add_newline_edit();
@@ -146,63 +540,105 @@ impl State {
     }
 
     pub fn move_cursor_ahead(&mut self) {
-        if self.cursor.location >= self.content.len() {
-            return;
+        if let Some(ch) = self.content[self.cursor.location..].chars().next() {
+            self.cursor.move_ahead(ch.len_utf8());
         }
-        self.cursor.move_char();
     }
 
     pub fn move_cursor_behind(&mut self) {
-        self.cursor.back_char();
+        if let Some(ch) = self.content[..self.cursor.location].chars().next_back() {
+            self.cursor.move_behind(ch.len_utf8());
+        }
     }
 
     pub fn move_cursor_up(&mut self) {
-        if let Some(slice) = self.content.get(..self.cursor.location) {
-            if let Some(loc) = slice.rfind('\n') {
-                let first_chars = self.cursor.location - loc;
-                if let Some(second_slice) = self.content.get(..loc) {
-                    if let Some(last_newline) = second_slice.rfind('\n') {
-                        if first_chars > loc - last_newline {
-                            self.cursor.move_behind(first_chars);
-                        } else {
-                            self.cursor
-                                .move_behind(self.cursor.location - last_newline - first_chars);
-                        }
-                    } else if first_chars > loc {
-                        self.cursor.move_behind(first_chars);
-                    } else {
-                        self.cursor
-                            .move_behind(self.cursor.location - first_chars + 1);
-                    }
-                }
-            }
+        let line = self.cursor_line();
+        if line > 0 {
+            self.set_cursor_line(line - 1);
         }
     }
 
     pub fn move_cursor_down(&mut self) {
-        if let Some(prev_slice) = self.content.get(..self.cursor.location) {
-            let loc1 = prev_slice.rfind('\n').unwrap_or(0);
-            let first_chars = self.cursor.location - loc1;
-            if let Some(after_slice) = self.content.get(self.cursor.location..) {
-                if let Some(after_chars) = after_slice.find('\n') {
-                    // check if next line has less number of characters
-                    if let Some(next_newline) = after_slice.get((after_chars + 1)..) {
-                        let next_newline_at = next_newline.find('\n').unwrap_or(self.content.len());
-                        if next_newline_at < first_chars {
-                            self.cursor.move_ahead(after_chars + next_newline_at + 1);
-                        } else if self.cursor.location + after_chars + first_chars
-                            < self.content.len()
-                        {
-                            self.cursor.move_ahead(after_chars + first_chars);
-                        } else {
-                            self.move_to_end();
-                        }
-                    }
-                }
-            }
+        let line = self.cursor_line();
+        if line + 1 < self.line_count() {
+            self.set_cursor_line(line + 1);
         }
     }
 
+    /// `gj` and `gk` move by screen rows of wrapped lines, keeping the column
+    pub fn move_display_line(&mut self, down: bool) {
+        let line = self.cursor_line();
+        let rows = wrap::line_rows(self, line);
+        let index = wrap::row_of(&rows, self.cursor.location);
+        let column = wrap::column_of(self, &rows[index], self.cursor.location);
+        let (target, last_row) = if down {
+            if index + 1 < rows.len() {
+                (rows[index + 1], index + 2 == rows.len())
+            } else if line + 1 < self.line_count() {
+                let next = wrap::line_rows(self, line + 1);
+                (next[0], next.len() == 1)
+            } else {
+                return;
+            }
+        } else if index > 0 {
+            (rows[index - 1], false)
+        } else if line > 0 {
+            let previous = wrap::line_rows(self, line - 1);
+            (previous[previous.len() - 1], true)
+        } else {
+            return;
+        };
+        self.cursor.location = wrap::location_at(self, &target, column, last_row);
+    }
+
+    /// `g0` and `g$` go to the first or last character on the screen row,
+    /// which is the visible part of the line while it is not wrapped
+    pub fn move_to_display_edge(&mut self, end: bool) {
+        let rows = wrap::line_rows(self, self.cursor_line());
+        let index = wrap::row_of(&rows, self.cursor.location);
+        let row = rows[index];
+        let column = match (self.options.wrap, end) {
+            (true, false) => 0,
+            (true, true) => usize::MAX,
+            (false, false) => self.viewport.left,
+            (false, true) => self.viewport.left + self.viewport.width.max(1) - 1,
+        };
+        let location = wrap::location_at(self, &row, column, false);
+        self.cursor.location = location.max(row.start);
+    }
+
+    /// start and end of the word at `location`, a run of blanks or of other
+    /// characters counts as a word too
+    pub fn word_at(&self, location: usize) -> (usize, usize) {
+        let class = |ch: char| {
+            if ch.is_alphanumeric() || ch == '_' {
+                0
+            } else if ch.is_whitespace() {
+                1
+            } else {
+                2
+            }
+        };
+        let line = self.line_of(location);
+        let (line_start, line_end) = (self.line_start(line), self.line_end(line));
+        let Some(current) = self.content[location..line_end].chars().next() else {
+            return (location, location);
+        };
+        let start = self.content[line_start..location]
+            .char_indices()
+            .rev()
+            .take_while(|(_, ch)| class(*ch) == class(current))
+            .last()
+            .map(|(index, _)| line_start + index)
+            .unwrap_or(location);
+        let end = self.content[location..line_end]
+            .char_indices()
+            .find(|(_, ch)| class(*ch) != class(current))
+            .map(|(index, _)| location + index)
+            .unwrap_or(line_end);
+        (start, end)
+    }
+
     pub fn move_by_a_word(&mut self) {
         if self.cursor.location == self.content.len() {
             return;
@@ -233,7 +669,11 @@ impl State {
                     self.cursor.move_ahead(l);
                 }
             } else {
-                self.cursor.move_ahead(slice.len() - 1);
+                let last = slice
+                    .char_indices()
+                    .next_back()
+                    .map_or(0, |(index, _)| index);
+                self.cursor.move_ahead(last);
             }
         }
     }
@@ -242,9 +682,180 @@ impl State {
         self.cursor.location = self.content.len();
     }
 
-    pub fn flush_file(&self) -> Result<()> {
+    pub fn append_at_line_end(&mut self) {
+        self.cursor.location = self.line_end(self.cursor_line());
+    }
+
+    pub fn insert_at_line_start(&mut self) {
+        self.cursor.location = self.line_start(self.cursor_line());
+    }
+
+    /// number of lines, a trailing newline does not start a new line
+    pub fn line_count(&self) -> usize {
+        let newlines = self.content.matches('\n').count();
+        if self.content.ends_with('\n') {
+            newlines.max(1)
+        } else {
+            newlines + 1
+        }
+    }
+
+    /// zero based line on which the cursor is present
+    pub fn cursor_line(&self) -> usize {
+        self.line_of(self.cursor.location)
+    }
+
+    pub fn line_of(&self, location: usize) -> usize {
+        let location = location.min(self.content.len());
+        self.content.as_bytes()[..location]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count()
+    }
+
+    /// byte offset of the first character of `line`
+    pub fn line_start(&self, line: usize) -> usize {
+        if line == 0 {
+            return 0;
+        }
+        self.content
+            .match_indices('\n')
+            .nth(line - 1)
+            .map(|(i, _)| i + 1)
+            .unwrap_or(self.content.len())
+    }
+
+    /// byte offset of the newline ending `line` or end of content
+    pub fn line_end(&self, line: usize) -> usize {
+        let start = self.line_start(line);
+        self.content[start..]
+            .find('\n')
+            .map(|i| start + i)
+            .unwrap_or(self.content.len())
+    }
+
+    /// moves to `line` keeping the column in characters where the line is
+    /// long enough
+    pub fn set_cursor_line(&mut self, line: usize) {
+        let column = self.content[self.line_start(self.cursor_line())..self.cursor.location]
+            .chars()
+            .count();
+        let line = line.min(self.line_count() - 1);
+        let (start, end) = (self.line_start(line), self.line_end(line));
+        self.cursor.location = self.content[start..end]
+            .char_indices()
+            .nth(column)
+            .map_or(end, |(index, _)| start + index);
+    }
+
+    pub fn move_to_first_non_blank(&mut self, line: usize) {
+        let line = line.min(self.line_count() - 1);
+        let start = self.line_start(line);
+        let blanks = self.content[start..self.line_end(line)]
+            .find(|ch: char| !ch.is_whitespace())
+            .unwrap_or(0);
+        self.cursor.location = start + blanks;
+    }
+
+    pub fn move_to_line(&mut self, line: usize) {
+        self.cursor.location = self.line_start(line.min(self.line_count() - 1));
+    }
+
+    /// removes the characters in `start..end`
+    pub fn delete_range(&mut self, start: usize, end: usize) {
+        let end = end.min(self.content.len());
+        if start >= end {
+            return;
+        }
+        self.changes += 1;
+        self.content.drain(start..end);
+        self.cursor.location = start;
+    }
+
+    /// removes lines `first..=last` together with their newlines
+    pub fn delete_lines(&mut self, first: usize, last: usize) {
+        self.changes += 1;
+        let mut start = self.line_start(first);
+        let mut end = self.line_end(last);
+        if end < self.content.len() {
+            end += 1;
+        } else {
+            // last line of the file, take the newline before it instead
+            start = start.saturating_sub(1);
+        }
+        self.content.drain(start..end);
+        self.move_to_line(first);
+    }
+
+    /// switches between the hex view of the bytes and the text, converting
+    /// the buffer without changing what would be written
+    pub fn set_binary(&mut self, binary: bool) -> Result<()> {
+        if self.options.binary == binary {
+            return Ok(());
+        }
+        if self.large.is_some() {
+            return Err(large::too_large());
+        }
+        let bytes = codec::encode(&self.content, &self.options)?;
+        // bytes before the cursor, counting a byte order mark like the hex view
+        let before = codec::encode(&self.content[..self.cursor.location], &self.options)?.len();
+        self.options.binary = binary;
+        self.content = codec::decode(bytes, &mut self.options)?;
+        // the hex view keeps the byte under the cursor, the text starts over
+        self.cursor.location = if binary {
+            crate::hex::offset_of(self, before)
+        } else {
+            0
+        };
+        self.hex_nibble = false;
+        Ok(())
+    }
+
+    /// writes the file for `:w` and friends, `force` being the `!` that
+    /// overrides `readonly` and writes over a file changed by another program
+    pub fn write_buffer(&mut self, force: bool) -> Result<()> {
+        if self.options.readonly && !force {
+            return Err(anyhow::Error::from(Error::new(
+                ErrorKind::PermissionDenied,
+                "E45: 'readonly' option is set (add ! to override)",
+            )));
+        }
+        // a removed file has nothing to lose
+        let exists = self.file.as_ref().is_some_and(|file| file.exists());
+        if exists && self.changed_on_disk() && !force {
+            return Err(anyhow::Error::from(Error::other(
+                "WARNING: The file has been changed since reading it (add ! to override)",
+            )));
+        }
+        self.flush_file()
+    }
+
+    pub fn flush_file(&mut self) -> Result<()> {
+        // the empty content would replace the whole file
+        if self.large.is_some() {
+            return Err(large::too_large());
+        }
+        if self.locked.is_some() {
+            return Err(anyhow::Error::from(Error::new(
+                ErrorKind::PermissionDenied,
+                "E1200: The file is encrypted and its key was not given, :X asks for it",
+            )));
+        }
         if let Some(file) = &self.file {
-            std::fs::write(file, self.content.clone())?;
+            let bytes = codec::encode(&self.content, &self.options)?;
+            // compressed in memory, so only the finished file is ever written
+            let bytes = match self.compression {
+                Some(compression) => compression.compress(&bytes)?,
+                None => bytes,
+            };
+            let bytes = match &self.key {
+                Some(key) => crypt::encrypt(&bytes, key)?,
+                None => bytes,
+            };
+            write_file(file, &bytes, &self.options)?;
+            self.saved_changes = self.changes;
+            self.file_stamp = FileStamp::of(file, &bytes);
+            self.resolve_path();
             Ok(())
         } else {
             Err(anyhow::Error::from(Error::new(
@@ -253,7 +864,125 @@ impl State {
             )))
         }
     }
+    /// stores text in the named register, or only the unnamed one when no
+    /// register was given, an uppercase name appends to the register
+    pub fn set_register(&mut self, name: Option<char>, text: String, linewise: bool) {
+        let register = Register { text, linewise };
+        if let Some(name) = name.filter(|name| *name != '"') {
+            let lower = name.to_ascii_lowercase();
+            if name.is_ascii_uppercase() {
+                if let Some(existing) = self.registers.get_mut(&lower) {
+                    if existing.linewise && !existing.text.ends_with('\n') {
+                        existing.text.push('\n');
+                    }
+                    existing.text.push_str(&register.text);
+                    existing.linewise |= register.linewise;
+                    let appended = existing.clone();
+                    self.registers.insert('"', appended);
+                    return;
+                }
+            }
+            self.registers.insert(lower, register.clone());
+        }
+        self.registers.insert('"', register);
+    }
+
+    pub fn register(&self, name: Option<char>) -> Option<&Register> {
+        self.registers
+            .get(&name.unwrap_or('"').to_ascii_lowercase())
+    }
+
+    /// text of lines `first..=last` with a newline after each line
+    pub fn lines_text(&self, first: usize, last: usize) -> String {
+        let mut text = String::from(&self.content[self.line_start(first)..self.line_end(last)]);
+        text.push('\n');
+        text
+    }
+
+    /// puts a register after the cursor, or before it when `before` is set,
+    /// whole lines go below or above the current line
+    pub fn put(&mut self, register: &Register, before: bool) {
+        self.changes += 1;
+        if register.linewise {
+            let line = self.cursor_line();
+            let text = register.text.strip_suffix('\n').unwrap_or(&register.text);
+            if before {
+                let start = self.line_start(line);
+                self.content.insert_str(start, &format!("{text}\n"));
+                self.cursor.location = start;
+            } else {
+                let end = self.line_end(line);
+                self.content.insert_str(end, &format!("\n{text}"));
+                self.cursor.location = end + 1;
+            }
+        } else {
+            if !before {
+                self.move_cursor_ahead();
+            }
+            let at = self.cursor.location;
+            self.content.insert_str(at, &register.text);
+            self.cursor.location = at + register.text.len();
+        }
+    }
+
+    /// keys typed for a command that is not complete yet, like `2"ad`
+    pub fn pending_keys(&self) -> String {
+        let mut keys = self
+            .count
+            .map(|count| count.to_string())
+            .unwrap_or_default();
+        if let Some(register) = self.pending_register {
+            keys.push('"');
+            keys.push(register);
+        }
+        keys.push_str(self.stacked_command.as_deref().unwrap_or_default());
+        keys
+    }
+
+    pub fn begin_change(&mut self) {
+        self.recording_change = Some(Change::default());
+    }
+
+    pub fn record_key(&mut self, key: KeyEvent) {
+        if let Some(change) = &mut self.recording_change {
+            change.keys.push(key);
+        }
+    }
+
+    pub fn set_change_count(&mut self, count: Option<usize>) {
+        if let Some(change) = &mut self.recording_change {
+            change.count = count;
+        }
+    }
+
+    /// the keys recorded so far become the change repeated by `.`
+    pub fn complete_change(&mut self, count: Option<usize>) {
+        if let Some(mut change) = self.recording_change.take() {
+            change.count = count;
+            self.last_change = Some(change);
+        }
+    }
+
+    pub fn discard_change(&mut self) {
+        self.recording_change = None;
+    }
+
+    /// `:x` and `ZZ` write the file only when it was changed before quitting
+    pub fn write_and_quit(&mut self, force: bool) -> Result<()> {
+        if self.is_modified() {
+            self.write_buffer(force)?;
+        }
+        self.end_program();
+        Ok(())
+    }
+
     pub fn end_program(&mut self) {
         self.running = false;
     }
 }
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,307 @@
+use anyhow::Result;
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{BufRead, BufReader, IsTerminal, Read, Write},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::UnixStream,
+    },
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossterm::event::Event;
+
+/// how often a watched file is looked at
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// everything the main loop waits for
+pub enum AppEvent {
+    Terminal(Event),
+    /// a timer set with [`Events::set_timer`] expired
+    Timer(usize),
+    /// the watched file was written, possibly by this program
+    FileChanged,
+    /// a line written by a job to its output
+    JobOutput(usize, String),
+    /// a job ended with its exit code
+    JobExited(usize, Option<i32>),
+    /// the program got `SIGCONT` after being stopped
+    Continued,
+}
+
+struct Timer {
+    id: usize,
+    at: Instant,
+    repeat: Option<Duration>,
+}
+
+/// a channel merging terminal input, timers, file watching and job output,
+/// the sources other than timers run on threads of their own
+pub struct Events {
+    sender: Sender<AppEvent>,
+    receiver: Receiver<AppEvent>,
+    /// events taken from the channel but not handled yet
+    queued: VecDeque<AppEvent>,
+    timers: Vec<Timer>,
+    /// the threads keep running while this is set
+    open: Arc<AtomicBool>,
+    /// set by the `SIGCONT` handler
+    continued: Arc<AtomicBool>,
+    handler: Option<signal_hook::SigId>,
+    /// wakes the input thread when written to, by `stop` and by signals
+    waker: Option<UnixStream>,
+    /// signals waking the input thread while it runs
+    wake_handlers: Vec<signal_hook::SigId>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        let continued = Arc::new(AtomicBool::new(false));
+        // without the handler `SIGCONT` would only be noticed by the next key press
+        let handler =
+            signal_hook::flag::register(signal_hook::consts::SIGCONT, Arc::clone(&continued)).ok();
+        Self {
+            sender,
+            receiver,
+            queued: VecDeque::new(),
+            timers: Vec::new(),
+            open: Arc::new(AtomicBool::new(true)),
+            continued,
+            handler,
+            waker: None,
+            wake_handlers: Vec::new(),
+            threads: Vec::new(),
+        }
+    }
+
+    /// a sender for sources outside of this module
+    pub fn sender(&self) -> Sender<AppEvent> {
+        self.sender.clone()
+    }
+
+    /// reads terminal events on a thread until the events are stopped, also
+    /// reporting when the program is continued after being stopped
+    pub fn listen_terminal(&mut self) -> Result<()> {
+        self.open.store(true, Ordering::SeqCst);
+        let sender = self.sender();
+        let open = Arc::clone(&self.open);
+        let continued = Arc::clone(&self.continued);
+        // crossterm reads the standard input when it is a terminal
+        let tty = if std::io::stdin().is_terminal() {
+            None
+        } else {
+            Some(fs::File::open("/dev/tty")?)
+        };
+        // the thread sleeps until the terminal or the other end of this has
+        // something to read, signals write to it as a resize would otherwise
+        // only reach crossterm's own signal pipe
+        let (mut wake, waker) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        for signal in [signal_hook::consts::SIGCONT, signal_hook::consts::SIGWINCH] {
+            let handler = signal_hook::low_level::pipe::register(signal, waker.try_clone()?)?;
+            self.wake_handlers.push(handler);
+        }
+        self.waker = Some(waker);
+        self.threads.push(thread::spawn(move || {
+            let tty = tty
+                .as_ref()
+                .map_or(libc::STDIN_FILENO, |tty| tty.as_raw_fd());
+            loop {
+                // whatever woke the thread, the bytes only served to wake it
+                while wake.read(&mut [0; 64]).is_ok_and(|read| read > 0) {}
+                if !open.load(Ordering::SeqCst) {
+                    break;
+                }
+                if continued.swap(false, Ordering::SeqCst)
+                    && sender.send(AppEvent::Continued).is_err()
+                {
+                    break;
+                }
+                // everything crossterm has read or can read without waiting
+                let mut sent = Ok(());
+                while sent.is_ok() {
+                    match crossterm::event::poll(Duration::ZERO) {
+                        Ok(true) => match crossterm::event::read() {
+                            Ok(event) => sent = sender.send(AppEvent::Terminal(event)),
+                            Err(_) => return,
+                        },
+                        Ok(false) => break,
+                        Err(_) => return,
+                    }
+                }
+                if sent.is_err() || !wait_readable(&[tty, wake.as_raw_fd()]) {
+                    break;
+                }
+            }
+        }));
+        Ok(())
+    }
+
+    /// reports when the modification time or size of `file` changes
+    pub fn watch_file(&mut self, file: PathBuf) {
+        self.open.store(true, Ordering::SeqCst);
+        let sender = self.sender();
+        let open = Arc::clone(&self.open);
+        let stamp = move || {
+            fs::metadata(&file)
+                .ok()
+                .map(|meta| (meta.modified().ok(), meta.len()))
+        };
+        self.threads.push(thread::spawn(move || {
+            let mut last = stamp();
+            loop {
+                // woken early by `stop`
+                thread::park_timeout(WATCH_INTERVAL);
+                if !open.load(Ordering::SeqCst) {
+                    break;
+                }
+                let current = stamp();
+                if current != last {
+                    last = current;
+                    if sender.send(AppEvent::FileChanged).is_err() {
+                        break;
+                    }
+                }
+            }
+        }));
+    }
+
+    /// ends the input and watching threads and waits for them, they can be
+    /// started again afterwards
+    pub fn stop(&mut self) {
+        self.open.store(false, Ordering::SeqCst);
+        // whoever stops the threads takes the terminal again anyway
+        self.continued.store(false, Ordering::SeqCst);
+        for handler in self.wake_handlers.drain(..) {
+            signal_hook::low_level::unregister(handler);
+        }
+        if let Some(mut waker) = self.waker.take() {
+            let _ = waker.write(&[0]);
+        }
+        for thread in self.threads.drain(..) {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+
+    /// runs `command` in the background, sending its output line by line
+    pub fn spawn_job(&self, id: usize, mut command: Command) -> Result<()> {
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let sender = self.sender();
+        thread::spawn(move || {
+            if let Some(stdout) = child.stdout.take() {
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    if sender.send(AppEvent::JobOutput(id, line)).is_err() {
+                        break;
+                    }
+                }
+            }
+            let code = child.wait().ok().and_then(|status| status.code());
+            let _ = sender.send(AppEvent::JobExited(id, code));
+        });
+        Ok(())
+    }
+
+    /// sends `AppEvent::Timer(id)` after `delay`, again every `delay` when
+    /// repeating, replacing a timer with the same id
+    pub fn set_timer(&mut self, id: usize, delay: Duration, repeat: bool) {
+        self.stop_timer(id);
+        self.timers.push(Timer {
+            id,
+            at: Instant::now() + delay,
+            repeat: repeat.then_some(delay),
+        });
+    }
+
+    pub fn stop_timer(&mut self, id: usize) {
+        self.timers.retain(|timer| timer.id != id);
+    }
+
+    /// there are events waiting, so drawing can wait until they are handled
+    pub fn pending(&mut self) -> bool {
+        self.queued.extend(self.receiver.try_iter());
+        !self.queued.is_empty()
+    }
+
+    /// waits for the next event or expired timer, a timer that is due comes
+    /// first so a stream of other events cannot hold it back
+    pub fn wait(&mut self) -> Result<AppEvent> {
+        let next = (0..self.timers.len()).min_by_key(|&index| self.timers[index].at);
+        if let Some(index) = next.filter(|&index| self.timers[index].at <= Instant::now()) {
+            return Ok(self.expire(index));
+        }
+        if let Some(event) = self.queued.pop_front() {
+            return Ok(event);
+        }
+        let Some(index) = next else {
+            return Ok(self.receiver.recv()?);
+        };
+        let wait = self.timers[index]
+            .at
+            .saturating_duration_since(Instant::now());
+        match self.receiver.recv_timeout(wait) {
+            Ok(event) => Ok(event),
+            Err(RecvTimeoutError::Timeout) => Ok(self.expire(index)),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// the event of the timer at `index`, setting a repeating one again
+    fn expire(&mut self, index: usize) -> AppEvent {
+        let timer = &mut self.timers[index];
+        let id = timer.id;
+        match timer.repeat {
+            // a timer held up for longer than its interval fires once
+            Some(every) => timer.at = (timer.at + every).max(Instant::now()),
+            None => {
+                self.timers.remove(index);
+            }
+        }
+        AppEvent::Timer(id)
+    }
+}
+
+/// blocks until one of `fds` can be read, false when waiting failed
+fn wait_readable(fds: &[RawFd]) -> bool {
+    let mut polled: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    // SAFETY: the pointer and length describe the `pollfd`s of `polled`
+    let ready = unsafe { libc::poll(polled.as_mut_ptr(), polled.len() as libc::nfds_t, -1) };
+    ready >= 0 || std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+}
+
+impl Drop for Events {
+    fn drop(&mut self) {
+        self.stop();
+        if let Some(handler) = self.handler.take() {
+            signal_hook::low_level::unregister(handler);
+        }
+    }
+}
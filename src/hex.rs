@@ -0,0 +1,220 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span, Text},
+};
+
+use crate::{codec, editor_mode::EditorMode, editor_state::State};
+
+/// bytes shown on a row of the hex view
+pub const BYTES_PER_ROW: usize = 16;
+/// columns before the first byte, the offset and `: `
+const OFFSET_WIDTH: usize = 10;
+
+/// the byte a character of a binary buffer stands for, every byte of the
+/// file being one character
+pub fn byte_of(ch: char) -> u8 {
+    codec::raw_byte(ch).unwrap_or(ch as u8)
+}
+
+/// number of bytes in the binary buffer
+pub fn byte_count(state: &State) -> usize {
+    state.content.chars().count()
+}
+
+/// index of the byte under the cursor
+pub fn cursor_byte(state: &State) -> usize {
+    state.content[..state.cursor.location].chars().count()
+}
+
+/// offset in the content of the byte at `index`, the end past the last byte
+pub fn offset_of(state: &State, index: usize) -> usize {
+    state
+        .content
+        .char_indices()
+        .nth(index)
+        .map_or(state.content.len(), |(offset, _)| offset)
+}
+
+fn move_to_byte(state: &mut State, index: usize) {
+    let last = byte_count(state).saturating_sub(1);
+    state.cursor.location = offset_of(state, index.min(last));
+    state.hex_nibble = false;
+}
+
+/// handles the keys of the hex view, returning false for keys handled like
+/// in text buffers, which are only the command line, `ZZ`, `ZQ` and `Ctrl-Z`
+pub fn handle_key(state: &mut State, editor_mode: &mut EditorMode, k: KeyEvent) -> bool {
+    match editor_mode {
+        EditorMode::Idle(_) if state.stacked_command.as_deref() == Some("g") => {
+            state.stacked_command = None;
+            if k.code == KeyCode::Char('g') {
+                move_to_byte(state, 0);
+            }
+            return true;
+        }
+        EditorMode::Idle(_) if state.stacked_command.is_none() => {}
+        EditorMode::Replace(_) => {
+            replace_key(state, editor_mode, k.code);
+            return true;
+        }
+        // a selection could only delete or insert bytes, so none is kept
+        EditorMode::Visual(_) => {
+            editor_mode.enter_idle_mode(None);
+            return true;
+        }
+        _ => return false,
+    }
+    let index = cursor_byte(state);
+    let row = index - index % BYTES_PER_ROW;
+    let page = state.viewport.height.max(1) * BYTES_PER_ROW;
+    if k.modifiers.contains(KeyModifiers::CONTROL) {
+        match k.code {
+            KeyCode::Char('f') => move_to_byte(state, index + page),
+            KeyCode::Char('b') => move_to_byte(state, index.saturating_sub(page)),
+            KeyCode::Char('d') => move_to_byte(state, index + page / 2),
+            KeyCode::Char('u') => move_to_byte(state, index.saturating_sub(page / 2)),
+            KeyCode::Char('z' | 'Z') => return false,
+            _ => {}
+        }
+        return true;
+    }
+    match k.code {
+        KeyCode::Char('h') | KeyCode::Left => move_to_byte(state, index.saturating_sub(1)),
+        KeyCode::Char('l') | KeyCode::Right => move_to_byte(state, index + 1),
+        KeyCode::Char('k') | KeyCode::Up => {
+            move_to_byte(state, index.saturating_sub(BYTES_PER_ROW))
+        }
+        // the last row may be shorter, stay put instead of jumping into it
+        KeyCode::Char('j') | KeyCode::Down if index + BYTES_PER_ROW < byte_count(state) => {
+            move_to_byte(state, index + BYTES_PER_ROW)
+        }
+        KeyCode::Char('0') => move_to_byte(state, row),
+        KeyCode::Char('$') => move_to_byte(state, row + BYTES_PER_ROW - 1),
+        KeyCode::Char('g') => state.stacked_command = Some(String::from("g")),
+        KeyCode::Char('G') => move_to_byte(state, usize::MAX),
+        KeyCode::Char('R') if byte_count(state) > 0 => {
+            state.hex_nibble = false;
+            editor_mode.enter_replace_mode(state.file.clone());
+        }
+        KeyCode::Char(':' | 'Z') | KeyCode::Esc => return false,
+        _ => {}
+    }
+    true
+}
+
+/// the wheel moves the cursor by whole rows, up for negative `rows`
+pub fn scroll(state: &mut State, rows: isize) {
+    let index = cursor_byte(state);
+    let distance = rows.unsigned_abs() * BYTES_PER_ROW;
+    if rows < 0 {
+        move_to_byte(state, index.saturating_sub(distance));
+    } else {
+        move_to_byte(state, index + distance);
+    }
+}
+
+/// hex digits overwrite the nibble under the cursor, the length of the
+/// file never changes
+fn replace_key(state: &mut State, editor_mode: &mut EditorMode, code: KeyCode) {
+    let index = cursor_byte(state);
+    match code {
+        KeyCode::Esc => {
+            state.hex_nibble = false;
+            editor_mode.enter_idle_mode(None);
+        }
+        KeyCode::Backspace | KeyCode::Left => {
+            if state.hex_nibble {
+                state.hex_nibble = false;
+            } else if index > 0 {
+                move_to_byte(state, index - 1);
+                state.hex_nibble = true;
+            }
+        }
+        KeyCode::Right => step_nibble(state, index),
+        KeyCode::Char(ch) => {
+            let Some(digit) = ch.to_digit(16) else {
+                return;
+            };
+            let Some(old) = state.content[state.cursor.location..].chars().next() else {
+                return;
+            };
+            let byte = byte_of(old);
+            let byte = if state.hex_nibble {
+                (byte & 0xf0) | digit as u8
+            } else {
+                (byte & 0x0f) | (digit as u8) << 4
+            };
+            let start = state.cursor.location;
+            let new = codec::raw_char(byte).to_string();
+            state
+                .content
+                .replace_range(start..start + old.len_utf8(), &new);
+            state.changes += 1;
+            step_nibble(state, index);
+        }
+        _ => {}
+    }
+}
+
+fn step_nibble(state: &mut State, index: usize) {
+    if !state.hex_nibble {
+        state.hex_nibble = true;
+    } else if index + 1 < byte_count(state) {
+        move_to_byte(state, index + 1);
+    }
+}
+
+/// the rows of the hex view from the top of the viewport, an offset, the
+/// bytes in hex and as text, with the screen position of the cursor
+pub fn view(state: &mut State) -> (Text<'static>, (usize, usize)) {
+    let count = byte_count(state);
+    let cursor = cursor_byte(state);
+    let rows = count.div_ceil(BYTES_PER_ROW).max(1);
+    state
+        .viewport
+        .follow(cursor / BYTES_PER_ROW, rows, state.options.scrolloff);
+    let top = state.viewport.top;
+    let height = state.viewport.height.max(1);
+
+    let reversed = Style::default().add_modifier(Modifier::REVERSED);
+    let mut bytes = state.content.chars().skip(top * BYTES_PER_ROW).map(byte_of);
+    let mut lines = Vec::new();
+    for row in top..(top + height).min(rows) {
+        let offset = row * BYTES_PER_ROW;
+        let mut hex = vec![Span::styled(
+            format!("{offset:08x}: "),
+            Style::default().fg(Color::DarkGray),
+        )];
+        let mut text = vec![Span::raw(" ")];
+        for column in 0..BYTES_PER_ROW {
+            let gap = if column == BYTES_PER_ROW / 2 { " " } else { "" };
+            let Some(byte) = bytes.next() else {
+                hex.push(Span::raw(format!("{gap}   ")));
+                continue;
+            };
+            let style = if offset + column == cursor {
+                reversed
+            } else {
+                Style::default()
+            };
+            hex.push(Span::raw(gap));
+            hex.push(Span::styled(format!("{byte:02x}"), style));
+            hex.push(Span::raw(" "));
+            let shown = if byte.is_ascii_graphic() || byte == b' ' {
+                char::from(byte)
+            } else {
+                '.'
+            };
+            text.push(Span::styled(shown.to_string(), style));
+        }
+        hex.extend(text);
+        lines.push(Line::from(hex));
+    }
+
+    let column = cursor % BYTES_PER_ROW;
+    let gap = usize::from(column >= BYTES_PER_ROW / 2);
+    let screen_column = OFFSET_WIDTH + column * 3 + gap + usize::from(state.hex_nibble);
+    let screen_row = cursor / BYTES_PER_ROW - top;
+    (Text::from(lines), (screen_column, screen_row))
+}
@@ -0,0 +1,121 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// names of special keys in vim's `<...>` notation
+const KEY_NAMES: [(&str, KeyCode); 17] = [
+    ("Esc", KeyCode::Esc),
+    ("CR", KeyCode::Enter),
+    ("BS", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab),
+    ("Del", KeyCode::Delete),
+    ("Insert", KeyCode::Insert),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("lt", KeyCode::Char('<')),
+    ("Space", KeyCode::Char(' ')),
+    ("Bar", KeyCode::Char('|')),
+];
+
+/// aliases accepted when reading but never written
+const KEY_ALIASES: [(&str, KeyCode); 4] = [
+    ("Enter", KeyCode::Enter),
+    ("Return", KeyCode::Enter),
+    ("NL", KeyCode::Enter),
+    ("Backspace", KeyCode::Backspace),
+];
+
+/// writes keys in vim's notation, `<Esc>`, `<CR>` or `<C-w>`, so macros can
+/// be kept and edited as text in registers
+pub fn to_notation(keys: &[KeyEvent]) -> String {
+    let mut text = String::new();
+    for key in keys {
+        // shift is already part of an uppercase character
+        let modifiers = key.modifiers - KeyModifiers::SHIFT;
+        let name = match key.code {
+            KeyCode::Char('<') => Some(String::from("lt")),
+            KeyCode::Char(ch) if modifiers.is_empty() => {
+                text.push(ch);
+                continue;
+            }
+            KeyCode::Char(ch) => Some(ch.to_string()),
+            KeyCode::F(n) => Some(format!("F{n}")),
+            code => KEY_NAMES
+                .iter()
+                .find(|(_, named)| *named == code)
+                .map(|(name, _)| name.to_string()),
+        };
+        let Some(name) = name else {
+            continue;
+        };
+        text.push('<');
+        if modifiers.contains(KeyModifiers::CONTROL) {
+            text.push_str("C-");
+        }
+        if modifiers.contains(KeyModifiers::ALT) {
+            text.push_str("A-");
+        }
+        if key.modifiers.contains(KeyModifiers::SHIFT) && !matches!(key.code, KeyCode::Char(_)) {
+            text.push_str("S-");
+        }
+        text.push_str(&name);
+        text.push('>');
+    }
+    text
+}
+
+/// reads keys written by [`to_notation`], anything that is not a known
+/// `<...>` name is taken literally and a newline is read as `<CR>`
+pub fn from_notation(text: &str) -> Vec<KeyEvent> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+        if ch == '<' {
+            if let Some((key, len)) = parse_special(rest) {
+                keys.push(key);
+                rest = &rest[len..];
+                continue;
+            }
+        }
+        keys.push(match ch {
+            '\n' => KeyEvent::from(KeyCode::Enter),
+            _ => KeyEvent::from(KeyCode::Char(ch)),
+        });
+        rest = &rest[ch.len_utf8()..];
+    }
+    keys
+}
+
+/// parses a `<...>` key at the start of `text` returning it with the length read
+fn parse_special(text: &str) -> Option<(KeyEvent, usize)> {
+    let end = text.find('>')?;
+    let mut name = &text[1..end];
+    let mut modifiers = KeyModifiers::NONE;
+    while name.len() > 2 && name.as_bytes()[1] == b'-' {
+        modifiers |= match name.as_bytes()[0].to_ascii_uppercase() {
+            b'C' => KeyModifiers::CONTROL,
+            b'A' | b'M' => KeyModifiers::ALT,
+            b'S' => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+        name = &name[2..];
+    }
+    let mut chars = name.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(ch), None) if !modifiers.is_empty() => KeyCode::Char(ch),
+        _ => KEY_NAMES
+            .iter()
+            .chain(KEY_ALIASES.iter())
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|(_, code)| *code)
+            .or_else(|| {
+                let n = name.strip_prefix(['F', 'f'])?.parse().ok()?;
+                Some(KeyCode::F(n))
+            })?,
+    };
+    Some((KeyEvent::new(code, modifiers), end + 1))
+}
@@ -0,0 +1,439 @@
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use memchr::{memchr, memchr_iter, memmem, memrchr};
+use memmap2::Mmap;
+use ratatui::text::{Line, Text};
+use std::{
+    fs::File,
+    io::{Error, ErrorKind},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    thread,
+};
+
+use crate::{editor_mode::EditorMode, editor_state::State, ui::display_width, wrap};
+
+/// bytes looked through for line starts before the index is updated
+const CHUNK: usize = 1 << 20;
+
+/// a file above the `largefile` size, shown read-only from a memory map
+/// instead of being read into the buffer
+pub struct LargeFile {
+    map: Arc<Mmap>,
+    /// offsets where lines start, filled in by a thread in the background
+    lines: Arc<Mutex<Vec<usize>>>,
+    /// bytes the background thread has looked through so far
+    indexed: Arc<AtomicUsize>,
+    /// tells the background thread to stop once the file is closed
+    stop: Arc<AtomicBool>,
+    /// offset of the byte under the cursor
+    pub cursor: usize,
+    /// offset of the first line on the screen
+    pub top: usize,
+}
+
+impl LargeFile {
+    /// maps `file` and starts indexing its lines
+    pub fn open(file: &Path) -> Result<Self> {
+        // SAFETY: the map is only read, another program truncating the file
+        // while it is open is the risk every pager with a memory map takes
+        let map = Arc::new(unsafe { Mmap::map(&File::open(file)?)? });
+        let lines = Arc::new(Mutex::new(vec![0]));
+        let indexed = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (bytes, found, done, stopped) =
+            (map.clone(), lines.clone(), indexed.clone(), stop.clone());
+        thread::spawn(move || index_lines(&bytes, &found, &done, &stopped));
+        Ok(Self {
+            map,
+            lines,
+            indexed,
+            stop,
+            cursor: 0,
+            top: 0,
+        })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// every line start is known
+    pub fn indexed(&self) -> bool {
+        self.indexed.load(Ordering::Acquire) == self.len()
+    }
+
+    /// offset of the start of the line with `offset`
+    pub fn line_start(&self, offset: usize) -> usize {
+        memrchr(b'\n', &self.map[..offset.min(self.len())]).map_or(0, |index| index + 1)
+    }
+
+    /// offset of the newline ending the line with `offset`, or the end of the file
+    pub fn line_end(&self, offset: usize) -> usize {
+        let offset = offset.min(self.len());
+        memchr(b'\n', &self.map[offset..]).map_or(self.len(), |index| offset + index)
+    }
+
+    /// start of the line after the one with `offset`
+    pub fn next_line(&self, offset: usize) -> Option<usize> {
+        let end = self.line_end(offset);
+        (end + 1 < self.len()).then_some(end + 1)
+    }
+
+    /// start of the line before the one with `offset`
+    pub fn previous_line(&self, offset: usize) -> Option<usize> {
+        let start = self.line_start(offset);
+        (start > 0).then(|| self.line_start(start - 1))
+    }
+
+    /// one based number of the line with `offset`, once the index got that far
+    pub fn line_number(&self, offset: usize) -> Option<usize> {
+        if offset >= self.indexed.load(Ordering::Acquire) && !self.indexed() {
+            return None;
+        }
+        let lines = self.lines.lock().unwrap_or_else(PoisonError::into_inner);
+        Some(lines.partition_point(|&start| start <= offset))
+    }
+
+    /// number of lines, once indexing is done
+    pub fn line_count(&self) -> Option<usize> {
+        self.indexed().then(|| {
+            self.lines
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .len()
+        })
+    }
+
+    /// start of the one based line `number`, the last line for a number past
+    /// the end, none while the index has not got that far
+    pub fn line_offset(&self, number: usize) -> Option<usize> {
+        let lines = self.lines.lock().unwrap_or_else(PoisonError::into_inner);
+        match lines.get(number.saturating_sub(1)) {
+            Some(&start) => Some(start),
+            None if self.indexed() => lines.last().copied(),
+            None => None,
+        }
+    }
+
+    /// first match of `pattern` after `from`, continuing at the top, with
+    /// whether the search wrapped around
+    pub fn find(&self, pattern: &str, from: usize) -> Option<(usize, bool)> {
+        let start = (from + 1).min(self.len());
+        memmem::find(&self.map[start..], pattern.as_bytes())
+            .map(|index| (start + index, false))
+            .or_else(|| memmem::find(&self.map, pattern.as_bytes()).map(|index| (index, true)))
+    }
+
+    /// moves `lines` lines down, or up for a negative number, keeping the column
+    fn move_lines(&mut self, lines: isize) {
+        let start = self.line_start(self.cursor);
+        let column = self.cursor - start;
+        let mut target = start;
+        for _ in 0..lines.unsigned_abs() {
+            let next = if lines > 0 {
+                self.next_line(target)
+            } else {
+                self.previous_line(target)
+            };
+            match next {
+                Some(next) => target = next,
+                None => break,
+            }
+        }
+        let end = self.line_end(target);
+        self.cursor = self.char_start((target + column).min(end.saturating_sub(1)).max(target));
+    }
+
+    /// scrolls the screen and the cursor together, like `Ctrl-D` and `Ctrl-U`
+    pub fn page(&mut self, lines: isize) {
+        self.scroll(lines);
+        self.move_lines(lines);
+    }
+
+    /// moves the first line on the screen `lines` lines down or up
+    fn scroll(&mut self, lines: isize) {
+        for _ in 0..lines.unsigned_abs() {
+            let next = if lines > 0 {
+                self.next_line(self.top)
+            } else {
+                self.previous_line(self.top)
+            };
+            match next {
+                Some(next) => self.top = next,
+                None => break,
+            }
+        }
+    }
+
+    /// the start of the UTF-8 character with the byte at `offset`
+    fn char_start(&self, mut offset: usize) -> usize {
+        while offset > 0 && offset < self.len() && self.map[offset] & 0xc0 == 0x80 {
+            offset -= 1;
+        }
+        offset
+    }
+
+    /// start of the character after the one under the cursor, staying on the line
+    fn next_char(&self) -> usize {
+        let end = self.line_end(self.cursor);
+        let mut next = self.cursor + 1;
+        while next < end && self.map[next] & 0xc0 == 0x80 {
+            next += 1;
+        }
+        if next < end {
+            next
+        } else {
+            self.cursor
+        }
+    }
+
+    /// moves the top line so the cursor line is shown with `scrolloff`
+    /// lines around it, counting only as many lines as fit on the screen
+    fn follow(&mut self, height: usize, scrolloff: usize) {
+        let line = self.line_start(self.cursor);
+        let mut rows = None;
+        if line >= self.top {
+            let mut start = self.top;
+            for row in 0..height {
+                if start == line {
+                    rows = Some(row);
+                    break;
+                }
+                match self.next_line(start) {
+                    Some(next) => start = next,
+                    None => break,
+                }
+            }
+        }
+        let back = match rows {
+            Some(row) if row < scrolloff => scrolloff,
+            Some(row) if row + scrolloff < height => return,
+            None if line < self.top => scrolloff,
+            _ => {
+                // the end of the file leaves no empty rows below it
+                let below = (0..scrolloff)
+                    .scan(line, |start, _| {
+                        *start = self.next_line(*start)?;
+                        Some(())
+                    })
+                    .count();
+                height - 1 - below
+            }
+        };
+        self.top = line;
+        self.scroll(-(back as isize));
+    }
+
+    /// position for the status line, the line number is there once indexed
+    pub fn status(&self) -> String {
+        let line = self
+            .line_number(self.cursor)
+            .map_or(String::from("?"), |line| line.to_string());
+        let progress = match self.line_count() {
+            Some(count) => count.to_string(),
+            None => format!(
+                "? (indexing {}%)",
+                percent(self.indexed.load(Ordering::Acquire), self.len())
+            ),
+        };
+        let column = self.cursor - self.line_start(self.cursor) + 1;
+        format!(
+            "[large] [RO] {line}/{progress}, {column}  {}%",
+            percent(self.cursor, self.len().saturating_sub(1))
+        )
+    }
+}
+
+impl Drop for LargeFile {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+    }
+}
+
+fn index_lines(map: &Mmap, lines: &Mutex<Vec<usize>>, indexed: &AtomicUsize, stop: &AtomicBool) {
+    let mut offset = 0;
+    while offset < map.len() && !stop.load(Ordering::Acquire) {
+        let end = (offset + CHUNK).min(map.len());
+        // a newline at the very end does not start another line
+        let found = memchr_iter(b'\n', &map[offset..end])
+            .map(|index| offset + index + 1)
+            .filter(|&start| start < map.len());
+        lines
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend(found);
+        offset = end;
+        indexed.store(offset, Ordering::Release);
+    }
+}
+
+fn percent(part: usize, whole: usize) -> usize {
+    if whole == 0 {
+        100
+    } else {
+        (part as u128 * 100 / whole as u128) as usize
+    }
+}
+
+/// the error of anything that would change a large file
+pub fn too_large() -> anyhow::Error {
+    anyhow::Error::from(Error::new(
+        ErrorKind::PermissionDenied,
+        "E21: Cannot make changes, the file is larger than 'largefile'",
+    ))
+}
+
+/// the file is above the `largefile` size in MiB, 0 making every file large
+pub fn is_large(len: u64, largefile: usize) -> bool {
+    len > 0 && len > (largefile as u64).saturating_mul(1024 * 1024)
+}
+
+/// handles the keys of a large file, returning false for keys handled like
+/// in other buffers: the command line, `ZZ`, `ZQ` and `Ctrl-Z`
+pub fn handle_key(state: &mut State, editor_mode: &mut EditorMode, k: KeyEvent) -> bool {
+    let height = state.viewport.height.max(1);
+    let count = state.count;
+    let Some(large) = &mut state.large else {
+        return false;
+    };
+    match editor_mode {
+        EditorMode::Idle(_) if state.stacked_command.as_deref() == Some("g") => {
+            state.stacked_command = None;
+            state.count = None;
+            if k.code == KeyCode::Char('g') {
+                go_to_line(large, editor_mode, count.unwrap_or(1));
+            }
+            return true;
+        }
+        EditorMode::Idle(_) if state.stacked_command.is_none() => {}
+        _ => return false,
+    }
+    if k.modifiers.contains(KeyModifiers::CONTROL) {
+        let lines = match k.code {
+            KeyCode::Char('f') => height.saturating_sub(2).max(1) as isize,
+            KeyCode::Char('b') => -(height.saturating_sub(2).max(1) as isize),
+            KeyCode::Char('d') => (height / 2).max(1) as isize,
+            KeyCode::Char('u') => -((height / 2).max(1) as isize),
+            KeyCode::Char('z' | 'Z') => return false,
+            _ => 0,
+        };
+        large.page(lines * count.unwrap_or(1) as isize);
+        state.count = None;
+        return true;
+    }
+    if let KeyCode::Char(digit @ '0'..='9') = k.code {
+        if digit != '0' || count.is_some() {
+            let digit = digit.to_digit(10).unwrap_or_default() as usize;
+            state.count = Some(count.unwrap_or(0).saturating_mul(10) + digit);
+            return true;
+        }
+    }
+    let times = count.unwrap_or(1);
+    match k.code {
+        KeyCode::Char('j') | KeyCode::Down | KeyCode::Enter => large.move_lines(times as isize),
+        KeyCode::Char('k') | KeyCode::Up => large.move_lines(-(times as isize)),
+        KeyCode::Char('l') | KeyCode::Right => {
+            for _ in 0..times {
+                large.cursor = large.next_char();
+            }
+        }
+        KeyCode::Char('h') | KeyCode::Left => {
+            let start = large.line_start(large.cursor);
+            for _ in 0..times {
+                if large.cursor > start {
+                    large.cursor = large.char_start(large.cursor - 1);
+                }
+            }
+        }
+        KeyCode::Char('0') => large.cursor = large.line_start(large.cursor),
+        KeyCode::Char('$') => {
+            let end = large.line_end(large.cursor);
+            large.cursor = large.char_start(end.saturating_sub(1).max(large.line_start(end)));
+        }
+        KeyCode::Char('g') => {
+            state.stacked_command = Some(String::from("g"));
+            return true;
+        }
+        KeyCode::Char('G') => go_to_line(large, editor_mode, count.unwrap_or(usize::MAX)),
+        KeyCode::Char('/') => {
+            editor_mode.enter_command_mode();
+            editor_mode.update_command('/');
+        }
+        KeyCode::Char('n') => {
+            let message = (0..times).try_fold(String::new(), |_, _| state.search_next());
+            editor_mode.enter_idle_mode(Some(message.unwrap_or_else(|e| e.to_string())));
+        }
+        KeyCode::Char(':' | 'Z') | KeyCode::Esc => return false,
+        KeyCode::Char(
+            'i' | 'I' | 'a' | 'A' | 'o' | 'O' | 'R' | 'x' | 'd' | 'p' | 'P' | 'v' | 'V' | '.',
+        ) => editor_mode.enter_idle_mode(Some(too_large().to_string())),
+        _ => {}
+    }
+    state.count = None;
+    true
+}
+
+/// `G` and `gg` with a count, which needs the index to have got that far
+fn go_to_line(large: &mut LargeFile, editor_mode: &mut EditorMode, number: usize) {
+    if number == usize::MAX {
+        // the last line is found from the end without the index
+        large.cursor = large.line_start(large.len().saturating_sub(1));
+        return;
+    }
+    match large.line_offset(number) {
+        Some(start) => large.cursor = start,
+        None => editor_mode.enter_idle_mode(Some(format!(
+            "line {number} is not indexed yet, try again in a moment"
+        ))),
+    }
+}
+
+/// the lines of the file from the top of the screen, cut to its width,
+/// with the screen position of the cursor
+pub fn view(state: &mut State) -> (Text<'static>, (usize, usize)) {
+    let height = state.viewport.height.max(1);
+    let width = state.viewport.width.max(1);
+    let scrolloff = state.options.scrolloff.min((height - 1) / 2);
+    let Some(large) = &mut state.large else {
+        return (Text::default(), (0, 0));
+    };
+    large.follow(height, scrolloff);
+
+    // only the start of a long line is decoded, enough for any screen column
+    let shown = |large: &LargeFile, start: usize, end: usize, columns: usize| {
+        let end = end.min(start + 4 * columns);
+        let bytes = &large.bytes()[start..end];
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+        String::from_utf8_lossy(bytes).into_owned()
+    };
+    let line = large.line_start(large.cursor);
+    let column = display_width(&shown(large, line, large.cursor, large.cursor - line));
+    state.viewport.follow_column(column);
+    let left = state.viewport.left;
+
+    let mut lines = Vec::new();
+    let mut start = Some(large.top);
+    let mut cursor_row = 0;
+    while let Some(line_start) = start.filter(|_| lines.len() < height) {
+        if line_start == line {
+            cursor_row = lines.len();
+        }
+        let text = shown(large, line_start, large.line_end(line_start), left + width);
+        let (from, to) = wrap::columns(&text, left, left + width);
+        lines.push(Line::from(text[from..to].to_string()));
+        start = large.next_line(line_start);
+    }
+    (Text::from(lines), (column - left, cursor_row))
+}
@@ -1,14 +1,65 @@
 pub mod args;
+pub mod codec;
+pub mod compress;
+pub mod crypt;
 pub mod cursor;
 pub mod editor_mode;
 pub mod editor_state;
+pub mod events;
+pub mod hex;
+pub mod keys;
+pub mod large;
+pub mod options;
+pub mod recovery;
+pub mod signs;
+pub mod statusline;
+pub mod swap;
 pub mod tui;
 pub mod ui;
+pub mod viewport;
+pub mod wrap;
+pub mod writer;
 
 #[cfg(test)]
 mod tests {
 
-    use crate::{cursor::Cursor, editor_state::State};
+    use crossterm::event::{
+        KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    };
+    use ratatui::style::Color;
+    use std::time::Duration;
+
+    use crate::{
+        compress::Compression,
+        crypt,
+        cursor::Cursor,
+        editor_mode::EditorMode,
+        editor_state::{self, State},
+        events::{AppEvent, Events},
+        hex,
+        keys::{from_notation, to_notation},
+        large, recovery,
+        signs::Sign,
+        statusline,
+        swap::{self, SwapChoice},
+        tui::{execute_keys, handle_event, handle_mouse, paste},
+        wrap,
+    };
+
+    /// characters as key presses, `\x1b` stands for escape and `\r` for enter
+    fn keys(keys: &str) -> Vec<KeyEvent> {
+        keys.chars()
+            .map(|ch| match ch {
+                '\x1b' => KeyEvent::from(KeyCode::Esc),
+                '\r' => KeyEvent::from(KeyCode::Enter),
+                _ => KeyEvent::from(KeyCode::Char(ch)),
+            })
+            .collect()
+    }
+
+    fn feed(state: &mut State, mode: &mut EditorMode, typed: &str) {
+        execute_keys(state, mode, &keys(typed)).unwrap();
+    }
 
     #[test]
     fn cursor_down_alt1() {
@@ -16,9 +67,7 @@ mod tests {
         let mut state = State {
             content: String::from("1234567\n12345\n123456789\n"),
             cursor,
-            running: true,
-            file: None,
-            stacked_command: None,
+            ..Default::default()
         };
 
         assert_eq!(state.cursor.location, 10);
@@ -31,9 +80,7 @@ mod tests {
         let mut state = State {
             content: String::from("1234567\n1234567\n123456789\n"),
             cursor,
-            running: true,
-            file: None,
-            stacked_command: None,
+            ..Default::default()
         };
 
         assert_eq!(state.cursor.location, 10);
@@ -46,9 +93,7 @@ mod tests {
         let mut state = State {
             content: String::from("123456789\n1234567\n123456789\n"),
             cursor,
-            running: true,
-            file: None,
-            stacked_command: None,
+            ..Default::default()
         };
 
         assert_eq!(state.cursor.location, 12);
@@ -62,11 +107,970 @@ mod tests {
         let mut state = State {
             content: String::from("12345678\n12345678\n12345678"),
             cursor,
-            running: true,
-            file: None,
-            stacked_command: None,
+            ..Default::default()
         };
         state.delete_line();
         assert_eq!(state.content, String::from("12345678\n12345678"));
     }
+
+    #[test]
+    fn normal_over_range() {
+        let mut state = State {
+            content: String::from("one\ntwo\nthree\n"),
+            ..Default::default()
+        };
+        let mut mode = EditorMode::Command(String::from("%normal A,"));
+        mode.apply_command(&mut state).unwrap();
+        assert_eq!(state.content, String::from("one,\ntwo,\nthree,\n"));
+    }
+
+    #[test]
+    fn normal_deleting_lines() {
+        let mut state = State {
+            content: String::from("1\n2\n3\n4"),
+            ..Default::default()
+        };
+        let mut mode = EditorMode::Command(String::from("2,3norm! Vd"));
+        mode.apply_command(&mut state).unwrap();
+        assert_eq!(state.content, String::from("1\n4"));
+    }
+
+    #[test]
+    fn repeat_insert() {
+        let mut state = State::default();
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "iab\x1b.");
+        assert_eq!(state.content, String::from("abab"));
+        feed(&mut state, &mut mode, "3.");
+        assert_eq!(state.content, String::from("ababababab"));
+    }
+
+    #[test]
+    fn repeat_delete_with_new_count() {
+        let mut state = State {
+            content: String::from("1\n2\n3\n4\n5\n6\n7"),
+            ..Default::default()
+        };
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "2dd.");
+        assert_eq!(state.content, String::from("5\n6\n7"));
+        feed(&mut state, &mut mode, "1.");
+        assert_eq!(state.content, String::from("6\n7"));
+        feed(&mut state, &mut mode, "iab\x1bx.");
+        assert_eq!(state.content, String::from("6\n7"));
+    }
+
+    #[test]
+    fn recursive_macro_stops_at_failing_motion() {
+        let mut state = State {
+            content: String::from("ab\ncd\nef"),
+            ..Default::default()
+        };
+        state.set_register(Some('a'), String::from("A;<Esc>j@a"), false);
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "@a");
+        assert_eq!(state.content, String::from("ab;\ncd;\nef;"));
+    }
+
+    #[test]
+    fn macro_count_and_repeats() {
+        let mut state = State::default();
+        state.set_register(Some('b'), String::from("i-<Esc>"), false);
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "3@b@@");
+        assert_eq!(state.content, String::from("----"));
+        feed(&mut state, &mut mode, ":normal A+\r2@:");
+        assert_eq!(state.content, String::from("----+++"));
+    }
+
+    #[test]
+    fn key_notation_round_trip() {
+        let typed = "i<b>\x1b:w\r";
+        let text = to_notation(&keys(typed));
+        assert_eq!(text, String::from("i<lt>b><Esc>:w<CR>"));
+        assert_eq!(from_notation(&text), keys(typed));
+        let control = from_notation("<C-w><c-W>");
+        assert_eq!(to_notation(&control), String::from("<C-w><C-W>"));
+    }
+
+    #[test]
+    fn edit_macro_as_text() {
+        let mut state = State {
+            content: String::from("ab\ncd"),
+            ..Default::default()
+        };
+        state.set_register(Some('a'), String::from("A:<Esc>"), false);
+        let mut mode = EditorMode::Idle(None);
+        // put the macro on a line of its own, fix it and yank it back
+        feed(&mut state, &mut mode, "O\x1b\"aP");
+        assert_eq!(state.content, String::from("A:<Esc>\nab\ncd"));
+        feed(&mut state, &mut mode, "5hxi;\x1b\"ayydd");
+        assert_eq!(state.content, String::from("ab\ncd"));
+        feed(&mut state, &mut mode, "@a");
+        assert_eq!(state.content, String::from("ab;\ncd"));
+    }
+
+    #[test]
+    fn viewport_follows_cursor_with_scrolloff() {
+        let mut state = State {
+            content: (0..100).map(|n| format!("{n}\n")).collect(),
+            ..Default::default()
+        };
+        state.viewport.height = 20;
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "30j");
+        state
+            .viewport
+            .follow(state.cursor_line(), state.line_count(), 5);
+        assert_eq!(state.viewport.top, 16);
+        feed(&mut state, &mut mode, "zt");
+        assert_eq!(state.viewport.top, 25);
+        feed(&mut state, &mut mode, "zb");
+        assert_eq!(state.viewport.top, 16);
+    }
+
+    #[test]
+    fn scrolling_moves_cursor_into_view() {
+        let mut state = State {
+            content: (0..100).map(|n| format!("{n}\n")).collect(),
+            ..Default::default()
+        };
+        state.viewport.height = 20;
+        let mut mode = EditorMode::Idle(None);
+        execute_keys(&mut state, &mut mode, &from_notation("<C-d>")).unwrap();
+        assert_eq!((state.viewport.top, state.cursor_line()), (10, 15));
+        execute_keys(&mut state, &mut mode, &from_notation("3<C-e>")).unwrap();
+        assert_eq!((state.viewport.top, state.cursor_line()), (13, 18));
+        execute_keys(&mut state, &mut mode, &from_notation("<C-f>")).unwrap();
+        assert_eq!((state.viewport.top, state.cursor_line()), (31, 36));
+        execute_keys(&mut state, &mut mode, &from_notation("<C-b>")).unwrap();
+        assert_eq!((state.viewport.top, state.cursor_line()), (13, 27));
+    }
+
+    #[test]
+    fn scrolling_over_a_line_taller_than_the_window() {
+        let mut state = State {
+            content: "x".repeat(500) + &"short\n".repeat(100),
+            ..Default::default()
+        };
+        state.content.insert(500, '\n');
+        state.viewport.height = 10;
+        state.viewport.width = 20;
+        let mut mode = EditorMode::Idle(None);
+        for (keys, top, line) in [("<C-e>", 1, 5), ("<C-d>", 6, 10), ("<C-e>", 7, 11)] {
+            // as drawn, the first line alone fills the window
+            let rows = wrap::visible_rows(&state);
+            state.viewport.shown = wrap::shown_lines(&state, &rows);
+            execute_keys(&mut state, &mut mode, &from_notation(keys)).unwrap();
+            assert_eq!((state.viewport.top, state.cursor_line()), (top, line));
+        }
+    }
+
+    #[test]
+    fn replace_mode_overwrites() {
+        let mut state = State {
+            content: String::from("abc\nd"),
+            ..Default::default()
+        };
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "lRxyzw\x1b");
+        assert_eq!(state.content, String::from("axyzw\nd"));
+
+        // backspace puts back what was typed over and removes what was added
+        let mut typed = keys("hhhRé\rq");
+        typed.extend([KeyEvent::from(KeyCode::Backspace); 4]);
+        execute_keys(&mut state, &mut mode, &typed).unwrap();
+        assert_eq!(state.content, String::from("axyzw\nd"));
+        assert_eq!(state.cursor.location, 1);
+        assert_eq!(crate::ui::display_width("a\u{4e2d}b"), 4);
+    }
+
+    #[test]
+    fn gutter_options_and_signs() {
+        let mut state = State::default();
+        let mut mode = EditorMode::Command(String::from("set nu rnu scl=yes nuw=6"));
+        mode.apply_command(&mut state).unwrap();
+        let mut mode = EditorMode::Command(String::from(r"set winbar=a\ b\\c stal=2"));
+        mode.apply_command(&mut state).unwrap();
+        assert_eq!(state.options.winbar, String::from(r"a b\c"));
+        assert_eq!(state.options.showtabline, 2);
+        let mut mode = EditorMode::Command(String::from("set nonu rnu? signcolumn?"));
+        let shown = mode.apply_command(&mut state).unwrap();
+        assert_eq!(shown, String::from("rnu signcolumn=yes"));
+        assert!(!state.options.number && state.options.numberwidth == 6);
+
+        let sign = |id, text: &str, priority| Sign {
+            id,
+            group: String::from("vcs"),
+            line: 3,
+            text: String::from(text),
+            color: Color::Green,
+            priority,
+        };
+        state.signs.place(sign(1, "+", 10));
+        state.signs.place(sign(2, "~", 20));
+        assert_eq!(state.signs.at(3).unwrap().text, "~");
+        state.signs.unplace("vcs", 2);
+        assert_eq!(state.signs.at(3).unwrap().text, "+");
+        state.signs.unplace_group("vcs");
+        assert!(state.signs.is_empty());
+    }
+
+    #[test]
+    fn statusline_format() {
+        let mut state = State {
+            file: Some(std::path::PathBuf::from("dir/main.rs")),
+            content: String::from("one\ntwo\nthree"),
+            ..Default::default()
+        };
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "jlx2\"a");
+        let text = |line: ratatui::text::Line| -> String {
+            line.spans
+                .iter()
+                .map(|span| span.content.as_ref())
+                .collect()
+        };
+        let line = statusline::render("%t%m%=%S%=%y %l/%L", &state, &mode, 30);
+        assert_eq!(text(line), String::from("main.rs[+]    2\"a   [rust] 2/3"));
+        let line = statusline::render("%-6.4f|%3c|%%|%{&ff}", &state, &mode, 30);
+        assert_eq!(text(line), String::from("<.rs  |  1|%|unix"));
+
+        // the full path is found when the file is opened or written
+        let dir = std::env::temp_dir().join(format!("mini-vim-full-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir
+            .join("..")
+            .join(format!("mini-vim-full-{}.txt", std::process::id()));
+        let mut state = State::begin_from_file(Some(file.clone())).unwrap();
+        let full = |state: &State| text(statusline::render("%F", state, &mode, 200));
+        assert_eq!(full(&state), file.display().to_string());
+        state.flush_file().unwrap();
+        let absolute = std::fs::canonicalize(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+        assert_eq!(full(&state), absolute.display().to_string());
+    }
+
+    #[test]
+    fn visual_selection_of_multibyte_characters() {
+        let mut state = State {
+            content: String::from("éa"),
+            ..Default::default()
+        };
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "v");
+        assert_eq!(mode.selection(&state), Some((0, 2)));
+        let line = statusline::render("%S", &state, &mode, 10);
+        assert_eq!(line.spans[0].content, "1");
+        feed(&mut state, &mut mode, "y");
+        assert_eq!(state.register(None).unwrap().text, "é");
+        feed(&mut state, &mut mode, "vd");
+        assert_eq!(state.content, "a");
+    }
+
+    #[test]
+    fn wrapped_lines_and_display_motions() {
+        let mut state = State {
+            content: String::from("  one two three four\nxy"),
+            ..Default::default()
+        };
+        state.viewport.width = 8;
+        let ranges = |state: &State| -> Vec<(usize, usize, usize)> {
+            wrap::line_rows(state, 0)
+                .iter()
+                .map(|row| (row.start, row.end, row.prefix))
+                .collect()
+        };
+        assert_eq!(ranges(&state), vec![(0, 8, 0), (8, 16, 0), (16, 20, 0)]);
+        let mut mode = EditorMode::Command(String::from(r"set lbr bri sbr=>\ "));
+        mode.apply_command(&mut state).unwrap();
+        assert_eq!(
+            ranges(&state),
+            vec![(0, 6, 0), (6, 10, 4), (10, 14, 4), (14, 16, 4), (16, 20, 4)]
+        );
+
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "lgj");
+        assert_eq!(state.cursor.location, 6);
+        feed(&mut state, &mut mode, "lg$");
+        assert_eq!(state.cursor.location, 9);
+        feed(&mut state, &mut mode, "3gj");
+        assert_eq!(state.cursor.location, 17);
+        feed(&mut state, &mut mode, "gkg0");
+        assert_eq!(state.cursor.location, 14);
+
+        // without wrapping the view scrolls sideways and the cursor stays on it
+        feed(&mut state, &mut mode, ":set nowrap\r");
+        state.cursor.location = 0;
+        assert_eq!(ranges(&state), vec![(0, 20, 0)]);
+        feed(&mut state, &mut mode, "4zl");
+        assert_eq!((state.viewport.left, state.cursor.location), (4, 4));
+        feed(&mut state, &mut mode, "g$");
+        assert_eq!(state.cursor.location, 11);
+        feed(&mut state, &mut mode, "ze");
+        assert_eq!(state.viewport.left, 4);
+        feed(&mut state, &mut mode, "zs2zh");
+        assert_eq!((state.viewport.left, state.cursor.location), (9, 11));
+    }
+
+    #[test]
+    fn bracketed_paste_and_file_on_disk() {
+        let mut state = State {
+            content: String::from("ab\n"),
+            ..Default::default()
+        };
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "a");
+        paste(&mut state, &mut mode, "x\r\ny");
+        feed(&mut state, &mut mode, "\x1b.");
+        assert_eq!(state.content, String::from("ax\nybx\ny\n"));
+        feed(&mut state, &mut mode, ":");
+        paste(&mut state, &mut mode, "set\nnu");
+        assert!(matches!(&mode, EditorMode::Command(c) if c == "setnu"));
+        feed(&mut state, &mut mode, "\x1bkkhR");
+        paste(&mut state, &mut mode, "pq");
+        feed(&mut state, &mut mode, "\x1bl.");
+        assert_eq!(state.content, String::from("pq\npqx\ny\n"));
+
+        let path = std::env::temp_dir().join(format!("mini-vim-disk-{}", std::process::id()));
+        std::fs::write(&path, "one\n").unwrap();
+        let mut state = State::begin_from_file(Some(path.clone())).unwrap();
+        assert!(!state.changed_on_disk());
+        std::fs::write(&path, "three\n").unwrap();
+        assert!(state.changed_on_disk());
+        state.flush_file().unwrap();
+        assert!(!state.changed_on_disk());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn mouse_clicks_drags_and_wheel() {
+        let mut state = State {
+            content: (0..30).map(|n| format!("word{n} other\n")).collect(),
+            ..Default::default()
+        };
+        state.viewport.height = 10;
+        state.viewport.width = 20;
+        state.viewport.origin = (4, 1);
+        let mouse = |kind, column, row| MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        };
+        let click = MouseEventKind::Down(MouseButton::Left);
+        let mut mode = EditorMode::Idle(None);
+        // nothing happens until the option names the mode
+        handle_mouse(&mut state, &mut mode, mouse(click, 6, 2));
+        assert_eq!(state.cursor.location, 0);
+        feed(&mut state, &mut mode, ":set mouse=nv\r");
+        handle_mouse(&mut state, &mut mode, mouse(click, 6, 2));
+        assert_eq!(state.cursor.location, 14);
+        handle_mouse(&mut state, &mut mode, mouse(click, 6, 2));
+        assert_eq!(mode.selection(&state), Some((12, 17)));
+
+        handle_mouse(&mut state, &mut mode, mouse(click, 0, 1));
+        assert!(matches!(mode, EditorMode::Idle(_)));
+        handle_mouse(
+            &mut state,
+            &mut mode,
+            mouse(MouseEventKind::Drag(MouseButton::Left), 7, 1),
+        );
+        assert_eq!(mode.selection(&state), Some((0, 4)));
+        feed(&mut state, &mut mode, "\x1b");
+
+        handle_mouse(
+            &mut state,
+            &mut mode,
+            mouse(MouseEventKind::ScrollDown, 0, 0),
+        );
+        assert_eq!(state.viewport.top, 3);
+        assert_eq!(state.cursor_line(), 7);
+    }
+
+    #[test]
+    fn event_channel_merges_sources() {
+        let mut events = Events::new();
+        events.set_timer(1, Duration::from_millis(20), true);
+        events.sender().send(AppEvent::FileChanged).unwrap();
+        assert!(events.pending());
+        assert!(matches!(events.wait().unwrap(), AppEvent::FileChanged));
+        assert!(matches!(events.wait().unwrap(), AppEvent::Timer(1)));
+        assert!(matches!(events.wait().unwrap(), AppEvent::Timer(1)));
+        events.stop_timer(1);
+
+        // a timer that is due goes before events that keep coming
+        events.set_timer(2, Duration::ZERO, false);
+        events.sender().send(AppEvent::FileChanged).unwrap();
+        assert!(events.pending());
+        assert!(matches!(events.wait().unwrap(), AppEvent::Timer(2)));
+        assert!(matches!(events.wait().unwrap(), AppEvent::FileChanged));
+
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "echo hi; echo there; exit 3"]);
+        events.spawn_job(7, command).unwrap();
+        assert!(matches!(events.wait().unwrap(), AppEvent::JobOutput(7, line) if line == "hi"));
+        let mut state = State::default();
+        let mut mode = EditorMode::Idle(None);
+        for _ in 0..2 {
+            let event = events.wait().unwrap();
+            handle_event(&mut state, &mut mode, event).unwrap();
+        }
+        assert_eq!(mode.display_mode(), "job 7 exited with 3");
+        assert!(!events.pending());
+    }
+
+    #[test]
+    fn recovery_copy_of_unsaved_buffer() {
+        let dir = std::env::temp_dir().join(format!("mini-vim-recovery-{}", std::process::id()));
+        let state = State {
+            file: Some(std::path::PathBuf::from("notes.txt")),
+            content: String::from("unsaved\n"),
+            ..Default::default()
+        };
+        let path = recovery::write_recovery_in(&dir, &state).unwrap();
+        let name = format!("notes.txt.{}.recovered", std::process::id());
+        assert_eq!(path.file_name().unwrap().to_str(), Some(name.as_str()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "unsaved\n");
+        std::fs::remove_dir_all(dir).unwrap();
+        assert!(recovery::recovery_dir().ends_with("mini-vim/recovery"));
+    }
+
+    #[test]
+    fn suspend_requests() {
+        let mut state = State::default();
+        let mut mode = EditorMode::Idle(None);
+        execute_keys(&mut state, &mut mode, &from_notation("<C-z>")).unwrap();
+        assert!(std::mem::take(&mut state.suspend_requested));
+        for command in ["sus", "suspend!", "st", "stop"] {
+            feed(&mut state, &mut mode, &format!(":{command}\r"));
+            assert!(std::mem::take(&mut state.suspend_requested), "{command}");
+        }
+        feed(&mut state, &mut mode, ":stopx\r");
+        assert!(!state.suspend_requested);
+    }
+
+    #[test]
+    fn quitting_protects_unsaved_changes() {
+        let path = std::env::temp_dir().join(format!("mini-vim-quit-{}", std::process::id()));
+        let mut state = State {
+            file: Some(path.clone()),
+            ..Default::default()
+        };
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "ZQ");
+        assert!(!state.running);
+
+        state.running = true;
+        feed(&mut state, &mut mode, "ione\x1b:q\r");
+        assert!(state.running && state.is_modified());
+        assert!(matches!(&mode, EditorMode::Idle(Some(m)) if m.starts_with("E37")));
+        feed(&mut state, &mut mode, ":x\r");
+        assert!(!state.running && !state.is_modified());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one");
+
+        // nothing changed, so `ZZ` quits without writing
+        std::fs::remove_file(&path).unwrap();
+        state.running = true;
+        feed(&mut state, &mut mode, "ZZ");
+        assert!(!state.running && !path.exists());
+
+        state.running = true;
+        feed(&mut state, &mut mode, "x:q!\r");
+        assert!(!state.running && state.is_modified());
+        state.running = true;
+        feed(&mut state, &mut mode, ":cq\r");
+        assert_eq!((state.running, state.exit_code), (false, 1));
+        feed(&mut state, &mut mode, ":cq 3\r");
+        assert_eq!(state.exit_code, 3);
+    }
+
+    #[test]
+    fn writes_keep_permissions_links_and_backups() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = std::env::temp_dir().join(format!("mini-vim-write-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file");
+        std::fs::write(&path, "old\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+
+        let mut state = State::begin_from_file(Some(link.clone())).unwrap();
+        state.content = String::from("new\n");
+        state.flush_file().unwrap();
+        assert!(std::fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        let meta = std::fs::metadata(&path).unwrap();
+        assert_eq!(meta.mode() & 0o777, 0o640);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+        // `writebackup` alone leaves nothing behind
+        assert!(!dir.join("file~").exists());
+
+        let hard = dir.join("hard");
+        std::fs::hard_link(&path, &hard).unwrap();
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, ":set backup bex=.bak\r");
+        state.content = String::from("newer\n");
+        state.flush_file().unwrap();
+        assert_eq!(std::fs::read_to_string(&hard).unwrap(), "newer\n");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("file.bak")).unwrap(),
+            "new\n"
+        );
+
+        feed(&mut state, &mut mode, ":set bdir=/nonexistent\r");
+        assert!(state.flush_file().is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn swap_files_recover_crashed_sessions() {
+        let dir = std::env::temp_dir().join(format!("mini-vim-swap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("text");
+        std::fs::write(&path, "disk\n").unwrap();
+        let no_swap = |_: &swap::SwapInfo| -> anyhow::Result<SwapChoice> { panic!("no swap file") };
+
+        let mut state = State::open_with_swap(Some(path.clone()), no_swap).unwrap();
+        assert_eq!(state.swap, Some(dir.join(".text.swp")));
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "ix\x1b");
+        assert!(swap::swap_outdated(&state));
+        let dirs_file = std::env::temp_dir().join(format!("mini-vim-dirs-{}", std::process::id()));
+        swap::update_swap_with(&mut state, &dirs_file).unwrap();
+        assert!(!swap::swap_outdated(&state));
+        let written = swap::read_swap(&dir.join(".text.swp")).unwrap();
+        assert_eq!(written.pid, std::process::id());
+        assert_eq!(written.content, "xdisk\n");
+        // other hidden files are skipped, whatever their names
+        std::fs::write(dir.join(".€€"), "").unwrap();
+        assert_eq!(swap::swap_files_in(&dir).len(), 1);
+        // `-r` looks where the swap file was written, not only in the current directory
+        let found = swap::recoverable_swaps(&dirs_file);
+        let canonical = std::fs::canonicalize(&dir).unwrap();
+        assert!(found
+            .iter()
+            .any(|(listed, swaps)| *listed == canonical && swaps.len() == 1));
+
+        // as if the session was killed, its process is gone
+        let text = std::fs::read_to_string(&written.path).unwrap();
+        let text = text.replace(&format!("pid={}", std::process::id()), "pid=999999999");
+        std::fs::write(&written.path, text).unwrap();
+        let recovered =
+            State::open_with_swap(Some(path.clone()), |_| Ok(SwapChoice::Recover)).unwrap();
+        assert_eq!(recovered.content, "xdisk\n");
+        assert!(recovered.is_modified());
+        assert_eq!(recovered.swap, Some(written.path.clone()));
+
+        let mut read_only =
+            State::open_with_swap(Some(path.clone()), |_| Ok(SwapChoice::ReadOnly)).unwrap();
+        assert_eq!(
+            (read_only.swap.clone(), read_only.content.as_str()),
+            (None, "disk\n")
+        );
+        feed(&mut read_only, &mut mode, ":w\r");
+        assert!(matches!(&mode, EditorMode::Idle(Some(m)) if m.starts_with("E45")));
+        feed(&mut read_only, &mut mode, ":w!\r");
+        assert!(matches!(&mode, EditorMode::Idle(Some(m)) if m.starts_with("File written")));
+
+        let edit = State::open_with_swap(Some(path.clone()), |_| Ok(SwapChoice::Edit)).unwrap();
+        assert_eq!(edit.swap, Some(dir.join(".text.swo")));
+        assert!(State::open_with_swap(Some(path.clone()), |_| Ok(SwapChoice::Quit)).is_err());
+        let delete = State::open_with_swap(Some(path.clone()), |_| Ok(SwapChoice::Delete)).unwrap();
+        assert_eq!(delete.swap, Some(written.path.clone()));
+        assert!(!written.path.exists());
+
+        // a normal end removes the swap file, `noswapfile` never writes one
+        feed(&mut state, &mut mode, "x");
+        swap::update_swap(&mut state).unwrap();
+        assert!(written.path.exists());
+        swap::remove_swap(&mut state);
+        assert!(!written.path.exists());
+        feed(&mut state, &mut mode, ":set noswf\r");
+        swap::update_swap(&mut state).unwrap();
+        assert!(!written.path.exists());
+        // a directory without swap files is dropped from the list
+        swap::recoverable_swaps(&dirs_file);
+        assert_eq!(std::fs::read(&dirs_file).unwrap(), b"");
+        std::fs::remove_file(dirs_file).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn external_changes_are_not_overwritten() {
+        let path = std::env::temp_dir().join(format!("mini-vim-external-{}", std::process::id()));
+        std::fs::write(&path, "one\n").unwrap();
+        let mut state = State::begin_from_file(Some(path.clone())).unwrap();
+        let mut mode = EditorMode::Idle(None);
+
+        // touching the file keeps its hash
+        let stamp = state.file_stamp.unwrap();
+        state.file_stamp = Some(editor_state::FileStamp {
+            modified: Some(std::time::SystemTime::UNIX_EPOCH),
+            ..stamp
+        });
+        assert!(!state.changed_on_disk());
+
+        std::fs::write(&path, "two\n").unwrap();
+        assert!(state.changed_on_disk());
+        feed(&mut state, &mut mode, "A!\x1b:w\r");
+        assert!(matches!(&mode, EditorMode::Idle(Some(m)) if m.starts_with("WARNING")));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "two\n");
+        feed(&mut state, &mut mode, ":w!\r");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one!\n");
+
+        std::fs::write(&path, "three\n").unwrap();
+        handle_event(&mut state, &mut mode, AppEvent::FileChanged).unwrap();
+        assert!(matches!(&mode, EditorMode::Idle(Some(m)) if m.starts_with("W11")));
+        assert_eq!(state.content, "one!\n");
+        feed(&mut state, &mut mode, ":set autoread\r");
+        handle_event(&mut state, &mut mode, AppEvent::FileChanged).unwrap();
+        assert_eq!(
+            (state.content.as_str(), state.is_modified()),
+            ("three\n", false)
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn line_endings_and_bom_survive_a_write() {
+        let path = std::env::temp_dir().join(format!("mini-vim-ff-{}", std::process::id()));
+        std::fs::write(&path, "\u{feff}one\r\ntwo\r\n").unwrap();
+        let mut state = State::begin_from_file(Some(path.clone())).unwrap();
+        assert_eq!(state.content, "one\ntwo\n");
+        assert_eq!((state.file_format(), state.options.bomb), ("dos", true));
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "jA!\x1b:w\r");
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"\xef\xbb\xbfone\r\ntwo!\r\n"
+        );
+
+        feed(&mut state, &mut mode, ":set ff=unix nobomb\r");
+        assert!(state.is_modified());
+        feed(&mut state, &mut mode, ":w\r");
+        assert_eq!(std::fs::read(&path).unwrap(), b"one\ntwo!\n");
+
+        // a lone `\r` keeps the file unix, mac needs to be in `fileformats`
+        std::fs::write(&path, "a\rb\r\nc\n").unwrap();
+        state.reload().unwrap();
+        assert_eq!(
+            (state.content.as_str(), state.file_format()),
+            ("a\rb\r\nc\n", "unix")
+        );
+        std::fs::write(&path, "a\rb\r").unwrap();
+        state.reload().unwrap();
+        assert_eq!(state.file_format(), "unix");
+        feed(&mut state, &mut mode, ":set ffs=unix,dos,mac\r");
+        state.reload().unwrap();
+        assert_eq!(
+            (state.content.as_str(), state.file_format()),
+            ("a\nb\n", "mac")
+        );
+        state.flush_file().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"a\rb\r");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn encodings_convert_and_keep_bytes() {
+        let path = std::env::temp_dir().join(format!("mini-vim-enc-{}", std::process::id()));
+        let mut mode = EditorMode::Idle(None);
+        // control characters too, latin1 is ISO-8859-1 and not windows-1252
+        std::fs::write(&path, b"caf\xe9\x85\n").unwrap();
+        let mut state = State::begin_from_file(Some(path.clone())).unwrap();
+        assert_eq!(
+            (state.content.as_str(), state.file_encoding()),
+            ("café\u{85}\n", "latin1")
+        );
+        feed(&mut state, &mut mode, "A\u{fc}\x1b:w\r");
+        assert_eq!(std::fs::read(&path).unwrap(), b"caf\xe9\x85\xfc\n");
+        feed(&mut state, &mut mode, "A\u{65e5}\x1b:w\r");
+        assert!(matches!(&mode, EditorMode::Idle(Some(m)) if m.starts_with("E513")));
+        feed(&mut state, &mut mode, ":set fenc=utf-8\r:w\r");
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "café\u{85}\u{fc}\u{65e5}\n"
+        );
+
+        let utf16 = b"\xff\xfea\x00\r\x00\n\x00";
+        std::fs::write(&path, utf16).unwrap();
+        state.reload().unwrap();
+        assert_eq!(
+            (state.content.as_str(), state.file_encoding()),
+            ("a\n", "utf-16le")
+        );
+        assert_eq!((state.file_format(), state.options.bomb), ("dos", true));
+        state.flush_file().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), utf16);
+
+        feed(&mut state, &mut mode, ":set fencs=utf-8,sjis\r");
+        std::fs::write(&path, b"\x93\xfa\x96\x7b\n").unwrap();
+        state.reload().unwrap();
+        assert_eq!(
+            (state.content.as_str(), state.file_encoding()),
+            ("日本\n", "sjis")
+        );
+
+        // nothing fits, the bytes come back unchanged, also a character
+        // looking like one standing for a byte
+        feed(&mut state, &mut mode, ":set fencs=utf-8\r");
+        let bytes = b"ok\xff\xef\x9e\x80\xe9\n";
+        std::fs::write(&path, bytes).unwrap();
+        state.reload().unwrap();
+        assert_eq!((state.file_encoding(), state.line_count()), ("raw", 1));
+        state.flush_file().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn decoded_text_is_edited_by_characters() {
+        let path = std::env::temp_dir().join(format!("mini-vim-chars-{}", std::process::id()));
+        std::fs::write(&path, b"\xe9t\xe9\nabc\n").unwrap();
+        let mut state = State::begin_from_file(Some(path.clone())).unwrap();
+        let mut mode = EditorMode::Idle(None);
+        assert_eq!(state.file_encoding(), "latin1");
+        feed(&mut state, &mut mode, "ll");
+        assert_eq!(state.cursor.location, 3);
+        let line = statusline::render("%v", &state, &mode, 10);
+        assert_eq!(line.spans[0].content, "3");
+        feed(&mut state, &mut mode, "j");
+        assert_eq!(state.cursor.location, 8);
+        feed(&mut state, &mut mode, "kh");
+        assert_eq!(state.cursor.location, 2);
+
+        feed(&mut state, &mut mode, "i\u{e0}\u{e8}");
+        assert_eq!(state.cursor.location, 6);
+        let mut typed = keys("\u{fc}");
+        typed.push(KeyEvent::from(KeyCode::Backspace));
+        typed.extend(keys("\x1b:w\r"));
+        execute_keys(&mut state, &mut mode, &typed).unwrap();
+        assert_eq!(state.content, "é\u{e0}\u{e8}té\nabc\n");
+        assert_eq!(std::fs::read(&path).unwrap(), b"\xe9\xe0\xe8t\xe9\nabc\n");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn hex_view_edits_bytes_in_place() {
+        let path = std::env::temp_dir().join(format!("mini-vim-hex-{}", std::process::id()));
+        let mut bytes: Vec<u8> = b"\x7fELF\x00\xff\r\n".to_vec();
+        bytes.extend(0..16);
+        std::fs::write(&path, &bytes).unwrap();
+        let mut state = State::begin_from_file(Some(path.clone())).unwrap();
+        assert!(state.options.binary);
+        state.viewport.height = 10;
+        let mut mode = EditorMode::Idle(None);
+
+        feed(&mut state, &mut mode, "llR5\x1b");
+        assert!(state.is_modified());
+        // other keys do nothing, the length never changes
+        feed(&mut state, &mut mode, "xilR1f\x1b:w\r");
+        bytes[2] = 0x5c;
+        bytes[3] = 0x1f;
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        assert_eq!(hex::cursor_byte(&state), 4);
+        feed(&mut state, &mut mode, "jj");
+        assert_eq!(hex::cursor_byte(&state), 20);
+        feed(&mut state, &mut mode, "jgg$");
+        assert_eq!(hex::cursor_byte(&state), 15);
+
+        let (text, cursor) = hex::view(&mut state);
+        let first: String = text.lines[0]
+            .spans
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect();
+        assert!(
+            first.starts_with("00000000: 7f 45 5c 1f 00 ff 0d 0a  00 01"),
+            "{first}"
+        );
+        assert!(first.ends_with("  .E\\............."), "{first}");
+        assert_eq!(cursor, (10 + 15 * 3 + 1, 0));
+
+        // the mouse only scrolls, clicks and drags select nothing to delete
+        feed(&mut state, &mut mode, ":set mouse=a\r");
+        let mouse = |kind| MouseEvent {
+            kind,
+            column: 12,
+            row: 0,
+            modifiers: KeyModifiers::NONE,
+        };
+        let click = MouseEventKind::Down(MouseButton::Left);
+        handle_mouse(&mut state, &mut mode, mouse(click));
+        handle_mouse(&mut state, &mut mode, mouse(click));
+        handle_mouse(
+            &mut state,
+            &mut mode,
+            mouse(MouseEventKind::Drag(MouseButton::Left)),
+        );
+        assert!(matches!(mode, EditorMode::Idle(_)));
+        handle_mouse(&mut state, &mut mode, mouse(MouseEventKind::ScrollDown));
+        assert_eq!(hex::cursor_byte(&state), 23);
+        handle_mouse(&mut state, &mut mode, mouse(MouseEventKind::ScrollUp));
+        assert_eq!(hex::cursor_byte(&state), 0);
+        mode.enter_visual_mode(0, false);
+        feed(&mut state, &mut mode, "d");
+        assert!(matches!(mode, EditorMode::Idle(_)));
+        assert_eq!(hex::byte_count(&state), bytes.len());
+
+        // as text the bytes are kept, the NUL only chose the view
+        feed(&mut state, &mut mode, ":set nobinary\r");
+        assert!(state.content.starts_with("\x7fE\\\x1f\0"));
+        feed(&mut state, &mut mode, ":set bin\r");
+        state.flush_file().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn large_files_are_mapped_read_only() {
+        let path = std::env::temp_dir().join(format!("mini-vim-large-{}", std::process::id()));
+        let text = "line 1\nline 2\nline 3\nline 4\nline 5\n";
+        std::fs::write(&path, text).unwrap();
+        let mut state = State::begin_from_file(Some(path.clone())).unwrap();
+        assert!(state.large.is_none());
+        assert!(!large::is_large(u64::MAX, usize::MAX));
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, ":set largefile=0\r");
+        state.reload().unwrap();
+        assert!(state.content.is_empty() && state.options.readonly);
+        let large = state.large.as_ref().unwrap();
+        for _ in 0..100 {
+            if large.indexed() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(large.line_count(), Some(5));
+
+        state.viewport.height = 3;
+        state.viewport.width = 20;
+        feed(&mut state, &mut mode, "jl");
+        assert_eq!(state.large.as_ref().unwrap().cursor, 8);
+        feed(&mut state, &mut mode, "4G");
+        let large = state.large.as_ref().unwrap();
+        assert_eq!(
+            (large.cursor, large.line_number(large.cursor)),
+            (21, Some(4))
+        );
+        feed(&mut state, &mut mode, "/line 2\r");
+        assert_eq!(mode.display_mode(), "search hit BOTTOM, continuing at TOP");
+        assert_eq!(state.large.as_ref().unwrap().cursor, 7);
+
+        // nothing changes the file, not even a forced write
+        feed(&mut state, &mut mode, "x");
+        assert!(mode.display_mode().starts_with("E21"));
+        feed(&mut state, &mut mode, ":w!\r");
+        assert!(mode.display_mode().starts_with("E21"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+        assert!(!state.is_modified());
+
+        feed(&mut state, &mut mode, "G");
+        let (view, cursor) = large::view(&mut state);
+        assert_eq!(view.lines[0].spans[0].content, "line 3");
+        assert_eq!(cursor, (0, 2));
+        assert!(state.large.as_ref().unwrap().status().contains("5/5"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compressed_files_are_edited_uncompressed() {
+        let dir = std::env::temp_dir();
+        let prefix = format!("mini-vim-compress-{}", std::process::id());
+        for (extension, compression) in [
+            ("gz", Compression::Gzip),
+            ("bz2", Compression::Bzip2),
+            ("xz", Compression::Xz),
+            ("zst", Compression::Zstd),
+        ] {
+            let path = dir.join(format!("{prefix}.txt.{extension}"));
+            std::fs::write(&path, compression.compress(b"hello\n").unwrap()).unwrap();
+            let mut state = State::begin_from_file(Some(path.clone())).unwrap();
+            assert_eq!(state.content, "hello\n");
+            assert_eq!(state.compression, Some(compression));
+            let mut mode = EditorMode::Idle(None);
+            feed(&mut state, &mut mode, "A!\x1b:w\r");
+            let written = std::fs::read(&path).unwrap();
+            assert_eq!(Compression::from_magic(&written), Some(compression));
+            assert_eq!(compression.decompress(&written).unwrap(), b"hello!\n");
+            assert!(!state.changed_on_disk());
+            std::fs::remove_file(path).unwrap();
+        }
+        // nothing but the written files was ever left next to them
+        let left: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().contains(&prefix))
+            .collect();
+        assert!(left.is_empty());
+
+        // a new file goes by its extension, an existing one by its bytes
+        let path = dir.join(format!("{prefix}.gz"));
+        let mut state = State::begin_from_file(Some(path.clone())).unwrap();
+        assert_eq!(state.compression, Some(Compression::Gzip));
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, "ihi\x1b:w\r");
+        assert_eq!(
+            Compression::Gzip
+                .decompress(&std::fs::read(&path).unwrap())
+                .unwrap(),
+            b"hi"
+        );
+        std::fs::write(&path, "plain\n").unwrap();
+        let state = State::begin_from_file(Some(path.clone())).unwrap();
+        assert_eq!(
+            (state.compression, state.content.as_str()),
+            (None, "plain\n")
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn encrypted_files_need_their_key() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("mini-vim-crypt-{}", std::process::id()));
+        std::fs::write(&path, "secret\n").unwrap();
+        let mut state = State::begin_from_file(Some(path.clone())).unwrap();
+        let mut mode = EditorMode::Idle(None);
+        feed(&mut state, &mut mode, ":X\rpw\r");
+        assert_eq!(mode.display_mode(), "Enter same key again: ");
+        feed(&mut state, &mut mode, "pw");
+        assert_eq!(mode.display_mode(), "Enter same key again: **");
+        feed(&mut state, &mut mode, "\r:w\r");
+        let written = std::fs::read(&path).unwrap();
+        assert!(crypt::is_encrypted(&written));
+        assert!(!written.windows(6).any(|window| window == b"secret"));
+        assert_eq!(crypt::decrypt(&written, "pw").unwrap(), b"secret\n");
+        // neither a swap file nor a recovery copy gets the text
+        assert!(!swap::swap_wanted(&state));
+        assert!(recovery::write_recovery_in(&dir, &state).is_err());
+
+        let mut state = State::begin_from_file(Some(path.clone())).unwrap();
+        assert!(state.locked.is_some() && state.content.is_empty() && state.swap.is_none());
+        let mut mode = EditorMode::Idle(None);
+        mode.enter_key_mode(true);
+        feed(&mut state, &mut mode, "wrong\r");
+        assert!(mode.display_mode().starts_with("E1200"));
+        feed(&mut state, &mut mode, ":w!\r");
+        assert!(mode.display_mode().starts_with("E1200"));
+        assert_eq!(std::fs::read(&path).unwrap(), written);
+        feed(&mut state, &mut mode, ":X\rpw\r");
+        assert_eq!(state.content, "secret\n");
+        feed(&mut state, &mut mode, "A!\x1b:w\r");
+        let written = std::fs::read(&path).unwrap();
+        assert_eq!(crypt::decrypt(&written, "pw").unwrap(), b"secret!\n");
+
+        feed(&mut state, &mut mode, ":X\ra\rb\r");
+        assert_eq!(mode.display_mode(), "Keys don't match!");
+        // an empty key writes the file unencrypted again
+        feed(&mut state, &mut mode, ":X\r\r\r:w\r");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "secret!\n");
+        std::fs::remove_file(path).unwrap();
+    }
 }
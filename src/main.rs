@@ -6,21 +6,99 @@ use mini_vim::{
     args::CustomArgs,
     editor_mode::EditorMode,
     editor_state::State,
-    tui::{initialize, quit_app, run_event_loop},
+    recovery::recovery_dir,
+    swap::{self, SwapChoice},
+    tui::{install_panic_hook, run_event_loop, TerminalGuard},
 };
 use ratatui::{prelude::CrosstermBackend, terminal};
 
 fn main() -> Result<()> {
     let args = CustomArgs::parse();
-    let state = State::begin_from_file(args.file.clone())?;
-    let editor_mode = EditorMode::new(args);
+    let mut state = match (&args.file, args.recover) {
+        (None, true) => {
+            list_recoverable();
+            return Ok(());
+        }
+        (Some(file), true) => {
+            State::open_with_swap(Some(file.clone()), |_| Ok(SwapChoice::Recover))?
+        }
+        (file, false) => State::begin_from_file(file.clone())?,
+    };
+    if args.binary {
+        state.set_binary(true)?;
+    }
+    let encrypt = args.encrypt;
+    let mut editor_mode = EditorMode::new(args);
+    // an encrypted file is read once its key is typed on the command line
+    if state.locked.is_some() {
+        editor_mode.enter_key_mode(true);
+    } else if encrypt {
+        editor_mode.enter_key_mode(false);
+    }
     let terminal = terminal::Terminal::new(CrosstermBackend::new(stderr()))?;
 
-    initialize()?;
+    install_panic_hook();
+    let exit_code = {
+        // restores the terminal however the loop ends
+        let mut guard = TerminalGuard::new(state)?;
 
-    run_event_loop(state, terminal, editor_mode)?;
+        run_event_loop(&mut guard.state, terminal, editor_mode)?;
 
-    quit_app()?;
+        guard.finish();
+        guard.state.exit_code
+    };
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
 
     Ok(())
 }
+
+/// prints what `-r` can recover, like `vim -r`
+fn list_recoverable() {
+    println!("Swap files found:");
+    let mut number = 0;
+    for (index, (dir, found)) in swap::recoverable_swaps(&swap::swap_dirs_file())
+        .iter()
+        .enumerate()
+    {
+        if index == 0 {
+            println!("   In current directory:");
+        } else {
+            println!("   In directory {}:", dir.display());
+        }
+        if found.is_empty() {
+            println!("      -- none --");
+        }
+        for found in found {
+            number += 1;
+            let running = if found.owner_running() {
+                " (STILL RUNNING)"
+            } else {
+                ""
+            };
+            println!("{number}.    {}", found.path.display());
+            println!(
+                "          owned by: process {} on {}{running}",
+                found.pid, found.host
+            );
+            println!("         file name: {}", found.file.display());
+        }
+    }
+
+    let dir = recovery_dir();
+    println!("   Recovery copies in {}:", dir.display());
+    let mut copies: Vec<_> = std::fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    copies.sort();
+    if copies.is_empty() {
+        println!("      -- none --");
+    }
+    for name in copies {
+        println!("      {name}");
+    }
+}
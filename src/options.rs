@@ -0,0 +1,359 @@
+use anyhow::Result;
+use std::io::{Error, ErrorKind};
+
+use crate::{codec, statusline::DEFAULT_STATUSLINE};
+
+/// settings changed with `:set`
+pub struct Options {
+    /// lines kept visible above and below the cursor
+    pub scrolloff: usize,
+    /// page and half page scrolling go to the first non-blank of the line
+    pub startofline: bool,
+    /// line numbers in the gutter
+    pub number: bool,
+    /// line numbers relative to the cursor, with `number` the cursor line stays absolute
+    pub relativenumber: bool,
+    /// minimal columns used for line numbers
+    pub numberwidth: usize,
+    pub signcolumn: SignColumn,
+    /// 2 shows a line with the open files above the text, 0 and 1 hide it
+    /// while only one file is open
+    pub showtabline: usize,
+    /// text of a line shown above the text, empty to hide it
+    pub winbar: String,
+    /// long lines continue on the next rows, otherwise the view scrolls sideways
+    pub wrap: bool,
+    /// wrap at a blank or punctuation instead of the last column
+    pub linebreak: bool,
+    /// wrapped rows keep the indent of their line
+    pub breakindent: bool,
+    /// text shown at the start of wrapped rows
+    pub showbreak: String,
+    /// modes using the mouse, `n` idle, `v` visual, `i` insert, `c` command
+    /// line and `a` all of them, empty leaves the mouse to the terminal
+    pub mouse: String,
+    /// keep a copy of the file as it was before writing
+    pub backup: bool,
+    /// make that copy during the write and remove it afterwards
+    pub writebackup: bool,
+    /// comma separated directories for backups, `.` is the directory of the file
+    pub backupdir: String,
+    /// appended to the file name of a backup
+    pub backupext: String,
+    /// keep unsaved changes in a swap file next to the file
+    pub swapfile: bool,
+    /// milliseconds without typing after which the swap file is written
+    pub updatetime: usize,
+    /// changes after which the swap file is written while typing on
+    pub updatecount: usize,
+    /// writing needs a `!`
+    pub readonly: bool,
+    /// read the file again when another program changed it and the buffer has no changes
+    pub autoread: bool,
+    /// line ending written after each line, found when reading the file
+    pub fileformat: FileFormat,
+    /// comma separated line endings tried when reading a file, in order
+    pub fileformats: String,
+    /// the file starts with a byte order mark
+    pub bomb: bool,
+    /// encoding the file is written in, found when reading it
+    pub fileencoding: String,
+    /// the file is edited as bytes in the hex view and written without any conversion
+    pub binary: bool,
+    /// comma separated encodings tried when reading a file, in order,
+    /// `ucs-bom` for any encoding named by a byte order mark
+    pub fileencodings: String,
+    /// size in MiB above which a file is mapped read-only instead of read
+    /// into the buffer, see [`crate::large`]
+    pub largefile: usize,
+    /// format of the status line, see [`crate::statusline::render`]
+    pub statusline: String,
+}
+
+/// when the sign column is shown in the gutter
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SignColumn {
+    /// only while a sign is placed
+    Auto,
+    Yes,
+    No,
+}
+
+/// line ending of a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    /// `\n`
+    Unix,
+    /// `\r\n`
+    Dos,
+    /// `\r`
+    Mac,
+}
+
+impl FileFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(Self::Unix),
+            "dos" => Some(Self::Dos),
+            "mac" => Some(Self::Mac),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Unix => "unix",
+            Self::Dos => "dos",
+            Self::Mac => "mac",
+        }
+    }
+
+    pub fn ending(self) -> &'static str {
+        match self {
+            Self::Unix => "\n",
+            Self::Dos => "\r\n",
+            Self::Mac => "\r",
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            scrolloff: 5,
+            startofline: false,
+            number: false,
+            relativenumber: false,
+            numberwidth: 4,
+            signcolumn: SignColumn::Auto,
+            showtabline: 1,
+            winbar: String::new(),
+            wrap: true,
+            linebreak: false,
+            breakindent: false,
+            showbreak: String::new(),
+            mouse: String::new(),
+            backup: false,
+            writebackup: true,
+            backupdir: String::from("."),
+            backupext: String::from("~"),
+            swapfile: true,
+            updatetime: 4000,
+            updatecount: 200,
+            readonly: false,
+            autoread: false,
+            fileformat: FileFormat::Unix,
+            fileformats: String::from("unix,dos"),
+            bomb: false,
+            binary: false,
+            fileencoding: String::from("utf-8"),
+            fileencodings: String::from("ucs-bom,utf-8,latin1"),
+            largefile: 100,
+            statusline: String::from(DEFAULT_STATUSLINE),
+        }
+    }
+}
+
+impl Options {
+    /// applies the arguments of `:set`, returning the values asked for with `?`
+    pub fn set(&mut self, arguments: &str) -> Result<String> {
+        let mut shown = Vec::new();
+        for argument in split_arguments(arguments) {
+            let argument = argument.as_str();
+            if let Some(name) = argument.strip_suffix('?') {
+                shown.push(self.show(name)?);
+            } else if let Some((name, value)) = argument.split_once('=') {
+                self.set_value(name, value)?;
+            } else if let Some(name) = argument.strip_prefix("no") {
+                self.set_flag(name, Some(false))?;
+            } else if let Some(name) = argument
+                .strip_prefix("inv")
+                .or_else(|| argument.strip_suffix('!'))
+            {
+                self.set_flag(name, None)?;
+            } else if self.set_flag(argument, Some(true)).is_err() {
+                // a number or string option without a value shows it
+                shown.push(self.show(argument)?);
+            }
+        }
+        Ok(shown.join(" "))
+    }
+
+    /// the mouse is used in the mode with flag `mode`
+    pub fn mouse_in(&self, mode: char) -> bool {
+        self.mouse.contains('a') || self.mouse.contains(mode)
+    }
+
+    fn flag(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "startofline" | "sol" => Some(&mut self.startofline),
+            "number" | "nu" => Some(&mut self.number),
+            "relativenumber" | "rnu" => Some(&mut self.relativenumber),
+            "wrap" => Some(&mut self.wrap),
+            "linebreak" | "lbr" => Some(&mut self.linebreak),
+            "breakindent" | "bri" => Some(&mut self.breakindent),
+            "backup" | "bk" => Some(&mut self.backup),
+            "writebackup" | "wb" => Some(&mut self.writebackup),
+            "swapfile" | "swf" => Some(&mut self.swapfile),
+            "readonly" | "ro" => Some(&mut self.readonly),
+            "autoread" | "ar" => Some(&mut self.autoread),
+            "bomb" => Some(&mut self.bomb),
+            "binary" | "bin" => Some(&mut self.binary),
+            _ => None,
+        }
+    }
+
+    /// sets a boolean option to `value` or toggles it for `None`
+    fn set_flag(&mut self, name: &str, value: Option<bool>) -> Result<()> {
+        let flag = self.flag(name).ok_or_else(|| unknown_option(name))?;
+        *flag = value.unwrap_or(!*flag);
+        Ok(())
+    }
+
+    fn set_value(&mut self, name: &str, value: &str) -> Result<()> {
+        match name {
+            "scrolloff" | "so" => self.scrolloff = parse_number(name, value)?,
+            "numberwidth" | "nuw" => self.numberwidth = parse_number(name, value)?.max(1),
+            "updatetime" | "ut" => self.updatetime = parse_number(name, value)?,
+            "updatecount" | "uc" => self.updatecount = parse_number(name, value)?,
+            "largefile" | "lf" => self.largefile = parse_number(name, value)?,
+            "showtabline" | "stal" => self.showtabline = parse_number(name, value)?.min(2),
+            "winbar" | "wbr" => self.winbar = value.to_string(),
+            "showbreak" | "sbr" => self.showbreak = value.to_string(),
+            "backupdir" | "bdir" => self.backupdir = value.to_string(),
+            "backupext" | "bex" if value.is_empty() => return Err(invalid_value(name, value)),
+            "backupext" | "bex" => self.backupext = value.to_string(),
+            "mouse" => {
+                if let Some(flag) = value.chars().find(|ch| !"nvica".contains(*ch)) {
+                    return Err(invalid_value(name, &flag.to_string()));
+                }
+                self.mouse = value.to_string();
+            }
+            "statusline" | "stl" => {
+                self.statusline = if value.is_empty() {
+                    String::from(DEFAULT_STATUSLINE)
+                } else {
+                    value.to_string()
+                }
+            }
+            "fileformat" | "ff" => {
+                self.fileformat =
+                    FileFormat::parse(value).ok_or_else(|| invalid_value(name, value))?
+            }
+            "fileencoding" | "fenc" => {
+                let known = codec::canonical(value).ok_or_else(|| invalid_value(name, value))?;
+                self.fileencoding = known.to_string();
+            }
+            "fileencodings" | "fencs" => {
+                let names = value.split(',').filter(|name| !name.is_empty());
+                let unknown = |name: &&str| *name != "ucs-bom" && codec::canonical(name).is_none();
+                if let Some(wrong) = names.clone().find(unknown) {
+                    return Err(invalid_value(name, wrong));
+                }
+                self.fileencodings = names.collect::<Vec<_>>().join(",");
+            }
+            "fileformats" | "ffs" => {
+                let names = value.split(',').filter(|name| !name.is_empty());
+                if let Some(wrong) = names.clone().find(|name| FileFormat::parse(name).is_none()) {
+                    return Err(invalid_value(name, wrong));
+                }
+                self.fileformats = names.collect::<Vec<_>>().join(",");
+            }
+            "signcolumn" | "scl" => {
+                self.signcolumn = match value {
+                    "auto" => SignColumn::Auto,
+                    "yes" => SignColumn::Yes,
+                    "no" => SignColumn::No,
+                    _ => return Err(invalid_value(name, value)),
+                }
+            }
+            _ => return Err(unknown_option(name)),
+        }
+        Ok(())
+    }
+
+    fn show(&mut self, name: &str) -> Result<String> {
+        if let Some(flag) = self.flag(name) {
+            return Ok(show_flag(name, *flag));
+        }
+        let value = match name {
+            "scrolloff" | "so" => self.scrolloff.to_string(),
+            "numberwidth" | "nuw" => self.numberwidth.to_string(),
+            "updatetime" | "ut" => self.updatetime.to_string(),
+            "updatecount" | "uc" => self.updatecount.to_string(),
+            "largefile" | "lf" => self.largefile.to_string(),
+            "showtabline" | "stal" => self.showtabline.to_string(),
+            "winbar" | "wbr" => self.winbar.clone(),
+            "showbreak" | "sbr" => self.showbreak.clone(),
+            "backupdir" | "bdir" => self.backupdir.clone(),
+            "backupext" | "bex" => self.backupext.clone(),
+            "mouse" => self.mouse.clone(),
+            "statusline" | "stl" => self.statusline.clone(),
+            "fileformat" | "ff" => self.fileformat.name().to_string(),
+            "fileformats" | "ffs" => self.fileformats.clone(),
+            "fileencoding" | "fenc" => self.fileencoding.clone(),
+            "fileencodings" | "fencs" => self.fileencodings.clone(),
+            "signcolumn" | "scl" => String::from(match self.signcolumn {
+                SignColumn::Auto => "auto",
+                SignColumn::Yes => "yes",
+                SignColumn::No => "no",
+            }),
+            _ => return Err(unknown_option(name)),
+        };
+        Ok(format!("{name}={value}"))
+    }
+}
+
+/// splits at whitespace that is not escaped with a backslash, so values
+/// like `winbar=a\ b` can hold spaces
+fn split_arguments(arguments: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut current = String::new();
+    let mut chars = arguments.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => current.extend(chars.next()),
+            ch if ch.is_whitespace() => {
+                if !current.is_empty() {
+                    split.push(std::mem::take(&mut current));
+                }
+            }
+            ch => current.push(ch),
+        }
+    }
+    if !current.is_empty() {
+        split.push(current);
+    }
+    split
+}
+
+fn show_flag(name: &str, value: bool) -> String {
+    if value {
+        name.to_string()
+    } else {
+        format!("no{name}")
+    }
+}
+
+fn parse_number(name: &str, value: &str) -> Result<usize> {
+    value.parse().map_err(|_| {
+        anyhow::Error::from(Error::new(
+            ErrorKind::InvalidInput,
+            format!("number required for {name}"),
+        ))
+    })
+}
+
+fn invalid_value(name: &str, value: &str) -> anyhow::Error {
+    anyhow::Error::from(Error::new(
+        ErrorKind::InvalidInput,
+        format!("invalid value for {name}: {value}"),
+    ))
+}
+
+fn unknown_option(name: &str) -> anyhow::Error {
+    anyhow::Error::from(Error::new(
+        ErrorKind::InvalidInput,
+        format!("unknown option: {name}"),
+    ))
+}
@@ -0,0 +1,37 @@
+use anyhow::Result;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::editor_state::State;
+
+/// where emergency copies are kept, `$XDG_STATE_HOME/mini-vim/recovery` or
+/// the same below `~/.local/state`, the temporary directory without a home
+pub fn recovery_dir() -> PathBuf {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+        .unwrap_or_else(env::temp_dir);
+    state_home.join("mini-vim").join("recovery")
+}
+
+/// writes the buffer to the recovery directory, returning the written file
+pub fn write_recovery(state: &State) -> Result<PathBuf> {
+    write_recovery_in(&recovery_dir(), state)
+}
+
+/// writes the buffer to `dir` as `<file name>.<pid>.recovered`
+pub fn write_recovery_in(dir: &Path, state: &State) -> Result<PathBuf> {
+    if state.key.is_some() {
+        return Err(anyhow::Error::from(std::io::Error::other(
+            "the text of an encrypted file is never written unencrypted",
+        )));
+    }
+    fs::create_dir_all(dir)?;
+    let name = state.file_name().unwrap_or("untitled");
+    let path = dir.join(format!("{name}.{}.recovered", std::process::id()));
+    fs::write(&path, &state.content)?;
+    Ok(path)
+}
@@ -0,0 +1,51 @@
+use ratatui::style::Color;
+
+/// a marker shown in the sign column next to a line
+#[derive(Clone)]
+pub struct Sign {
+    pub id: usize,
+    /// subsystem that placed the sign, like `diagnostics` or `vcs`
+    pub group: String,
+    pub line: usize,
+    /// one or two columns of text
+    pub text: String,
+    pub color: Color,
+    /// the highest priority sign is shown when a line has several
+    pub priority: i32,
+}
+
+/// signs placed by other subsystems, identified by their group and id
+#[derive(Default)]
+pub struct Signs {
+    placed: Vec<Sign>,
+}
+
+impl Signs {
+    /// places a sign, replacing one with the same group and id
+    pub fn place(&mut self, sign: Sign) {
+        self.unplace(&sign.group, sign.id);
+        self.placed.push(sign);
+    }
+
+    pub fn unplace(&mut self, group: &str, id: usize) {
+        self.placed
+            .retain(|sign| sign.group != group || sign.id != id);
+    }
+
+    /// removes every sign of a subsystem, e.g. before placing fresh diagnostics
+    pub fn unplace_group(&mut self, group: &str) {
+        self.placed.retain(|sign| sign.group != group);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.placed.is_empty()
+    }
+
+    /// the sign to show for `line`
+    pub fn at(&self, line: usize) -> Option<&Sign> {
+        self.placed
+            .iter()
+            .filter(|sign| sign.line == line)
+            .max_by_key(|sign| sign.priority)
+    }
+}
@@ -0,0 +1,248 @@
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+
+use crate::{editor_mode::EditorMode, editor_state::State, ui::display_width};
+
+/// shown while the `statusline` option is not changed
+pub const DEFAULT_STATUSLINE: &str =
+    "%#Mode# %{mode()} %* %f %m%=%S  %y %{&fenc} %{&ff}  %l,%c  %P ";
+
+/// colors of the status line outside of highlight groups
+pub fn base_style() -> Style {
+    Style::default().bg(Color::DarkGray).fg(Color::White)
+}
+
+/// style of a `%#Group#` highlight group
+fn highlight(group: &str, editor_mode: &EditorMode) -> Style {
+    let style = base_style();
+    match group {
+        // follows the mode like the old footer did
+        "Mode" => match editor_mode {
+            EditorMode::Command(_) | EditorMode::Key(_) => style.bg(Color::Blue).fg(Color::White),
+            EditorMode::Edit(_) => style.bg(Color::LightGreen).fg(Color::Black),
+            EditorMode::Replace(_) => style.bg(Color::LightRed).fg(Color::Black),
+            EditorMode::Idle(_) => style.bg(Color::Cyan).fg(Color::Black),
+            EditorMode::Visual(_) => style.bg(Color::Yellow).fg(Color::Black),
+        }
+        .add_modifier(Modifier::BOLD),
+        "ModeMsg" | "Title" => style.add_modifier(Modifier::BOLD),
+        "ErrorMsg" => style.bg(Color::Red).fg(Color::White),
+        "WarningMsg" | "Modified" => style.fg(Color::LightRed),
+        "Directory" => style.fg(Color::LightBlue),
+        "User1" => style.fg(Color::LightYellow),
+        "User2" => style.fg(Color::LightGreen),
+        "User3" => style.fg(Color::LightCyan),
+        "User4" => style.fg(Color::LightMagenta),
+        "User5" => style.fg(Color::LightBlue),
+        "User6" => style.fg(Color::LightRed),
+        "User7" => style.bg(Color::Gray).fg(Color::Black),
+        "User8" => style.bg(Color::Blue).fg(Color::White),
+        "User9" => style.bg(Color::Magenta).fg(Color::White),
+        _ => style,
+    }
+}
+
+/// expands a vim like status line format to fit `width` columns
+///
+/// `%f`, `%F` and `%t` give the file name, `%m` the modified flag, `%y` the file type,
+/// `%l`, `%L`, `%c`, `%v`, `%p` and `%P` the position, `%S` the pending keys or the
+/// size of the selection and `%{&fenc}`, `%{&ff}`, `%{&ft}` or `%{mode()}` other values.
+/// `%=` separates groups aligned left, center and right and `%#Group#` or `%1*`
+/// change the highlight. Items take a width like `%-10.20f`.
+pub fn render(
+    format: &str,
+    state: &State,
+    editor_mode: &EditorMode,
+    width: usize,
+) -> Line<'static> {
+    let mut sections: Vec<Vec<Span<'static>>> = vec![Vec::new()];
+    let mut style = base_style();
+    let mut literal = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            literal.push(ch);
+            continue;
+        }
+        let mut left_align = false;
+        if chars.peek() == Some(&'-') {
+            left_align = true;
+            chars.next();
+        }
+        let min_width = read_number(&mut chars);
+        let max_width = if chars.peek() == Some(&'.') {
+            chars.next();
+            read_number(&mut chars)
+        } else {
+            None
+        };
+        let Some(item) = chars.next() else {
+            break;
+        };
+        let value = match item {
+            '%' => Some(String::from("%")),
+            '=' | '#' | '*' => {
+                flush(&mut sections, &mut literal, style);
+                match item {
+                    '=' => sections.push(Vec::new()),
+                    '#' => {
+                        let group: String = chars.by_ref().take_while(|ch| *ch != '#').collect();
+                        style = highlight(&group, editor_mode);
+                    }
+                    _ => {
+                        style = match min_width {
+                            Some(n @ 1..=9) => highlight(&format!("User{n}"), editor_mode),
+                            _ => base_style(),
+                        }
+                    }
+                }
+                continue;
+            }
+            '{' => {
+                let expression: String = chars.by_ref().take_while(|ch| *ch != '}').collect();
+                evaluate(&expression, state, editor_mode)
+            }
+            // truncation point, the line is clipped at the right instead
+            '<' => continue,
+            item => expand(item, state, editor_mode),
+        };
+        if let Some(value) = value {
+            literal.push_str(&fit(value, min_width, max_width, left_align));
+        }
+    }
+    flush(&mut sections, &mut literal, style);
+
+    // spread the free columns over the `%=` separators
+    let used: usize = sections
+        .iter()
+        .flatten()
+        .map(|span| display_width(&span.content))
+        .sum();
+    let gaps = sections.len() - 1;
+    let free = width.saturating_sub(used);
+    let mut spans = Vec::new();
+    for (index, section) in sections.into_iter().enumerate() {
+        if index > 0 {
+            let fill = free / gaps + usize::from(index <= free % gaps);
+            spans.push(Span::styled(" ".repeat(fill), base_style()));
+        }
+        spans.extend(section);
+    }
+    Line::from(spans)
+}
+
+fn read_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<usize> {
+    let mut number = None;
+    while let Some(digit) = chars.peek().and_then(|ch| ch.to_digit(10)) {
+        number = Some(number.unwrap_or(0) * 10 + digit as usize);
+        chars.next();
+    }
+    number
+}
+
+fn flush(sections: &mut [Vec<Span<'static>>], literal: &mut String, style: Style) {
+    if !literal.is_empty() {
+        if let Some(section) = sections.last_mut() {
+            section.push(Span::styled(std::mem::take(literal), style));
+        }
+    }
+}
+
+/// pads to `min_width` and cuts to `max_width` keeping the end, like vim does
+fn fit(value: String, min_width: Option<usize>, max_width: Option<usize>, left: bool) -> String {
+    let mut value = value;
+    if let Some(max) = max_width {
+        let count = value.chars().count();
+        if count > max && max > 0 {
+            let kept: String = value.chars().skip(count - max + 1).collect();
+            value = format!("<{kept}");
+        }
+    }
+    let min = min_width.unwrap_or(0);
+    if left {
+        format!("{value:<min$}")
+    } else {
+        format!("{value:>min$}")
+    }
+}
+
+fn expand(item: char, state: &State, editor_mode: &EditorMode) -> Option<String> {
+    let line = state.cursor_line();
+    let line_count = state.line_count();
+    let line_start = state.line_start(line);
+    Some(match item {
+        'f' => state
+            .file
+            .as_ref()
+            .map(|file| file.display().to_string())
+            .unwrap_or_else(|| String::from("[No Name]")),
+        'F' => state
+            .full_path
+            .as_ref()
+            .or(state.file.as_ref())
+            .map(|file| file.display().to_string())
+            .unwrap_or_else(|| String::from("[No Name]")),
+        't' => state.file_name().unwrap_or("[No Name]").to_string(),
+        'm' => {
+            if state.is_modified() {
+                String::from("[+]")
+            } else {
+                String::new()
+            }
+        }
+        'M' => {
+            if state.is_modified() {
+                String::from(",+")
+            } else {
+                String::new()
+            }
+        }
+        'y' => state
+            .file_type()
+            .map(|file_type| format!("[{file_type}]"))
+            .unwrap_or_default(),
+        'Y' => state.file_type().unwrap_or_default().to_string(),
+        'l' => (line + 1).to_string(),
+        'L' => line_count.to_string(),
+        'c' => (state.cursor.location - line_start + 1).to_string(),
+        'v' => (display_width(&state.content[line_start..state.cursor.location]) + 1).to_string(),
+        'p' => ((line + 1) * 100 / line_count).to_string(),
+        'P' => {
+            let bottom = state.viewport.bottom(line_count);
+            match (state.viewport.top == 0, bottom + 1 >= line_count) {
+                (true, true) => String::from("All"),
+                (true, false) => String::from("Top"),
+                (false, true) => String::from("Bot"),
+                (false, false) => format!("{}%", state.viewport.top * 100 / line_count),
+            }
+        }
+        'S' => match editor_mode.selection(state) {
+            Some((start, end)) => {
+                let lines =
+                    state.line_of(end.saturating_sub(1).max(start)) - state.line_of(start) + 1;
+                if lines > 1
+                    || matches!(editor_mode, EditorMode::Visual(selection) if selection.linewise)
+                {
+                    lines.to_string()
+                } else {
+                    state.content[start..end].chars().count().to_string()
+                }
+            }
+            None => state.pending_keys(),
+        },
+        _ => return None,
+    })
+}
+
+/// the small set of `%{}` expressions that are understood
+fn evaluate(expression: &str, state: &State, editor_mode: &EditorMode) -> Option<String> {
+    Some(match expression.trim() {
+        "&fenc" | "&fileencoding" => state.file_encoding().to_string(),
+        "&ff" | "&fileformat" => state.file_format().to_string(),
+        "&ft" | "&filetype" => state.file_type().unwrap_or_default().to_string(),
+        "mode()" => editor_mode.mode_name().to_string(),
+        _ => return None,
+    })
+}
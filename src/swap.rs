@@ -0,0 +1,358 @@
+use anyhow::Result;
+use std::{
+    ffi::OsStr,
+    fs,
+    io::{self, BufRead, Error, ErrorKind, IsTerminal, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{editor_state::State, recovery::recovery_dir, writer::write_private};
+
+/// first line of every swap file
+const MAGIC: &str = "mini-vim swap file";
+
+/// what a swap file left by another session says
+pub struct SwapInfo {
+    pub path: PathBuf,
+    pub pid: u32,
+    pub host: String,
+    /// the edited file as an absolute path
+    pub file: PathBuf,
+    pub content: String,
+}
+
+impl SwapInfo {
+    /// the session that wrote the swap file still runs on this machine
+    pub fn owner_running(&self) -> bool {
+        self.host == host_name() && process_running(self.pid)
+    }
+}
+
+/// answer to the question asked when a file has a swap file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwapChoice {
+    /// continue with the text of the swap file
+    Recover,
+    /// open the file without a swap file and refuse writing without `!`
+    ReadOnly,
+    /// open the file with a swap file of its own
+    Edit,
+    /// remove the swap file and open the file
+    Delete,
+    Quit,
+}
+
+/// names tried for the swap file of `file`, `.name.swp` first and
+/// `.name.swo` when that is taken and so on, like vim
+fn swap_names(file: &Path) -> impl Iterator<Item = PathBuf> {
+    let dir = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    ('a'..='p')
+        .rev()
+        .map(move |letter| dir.join(format!(".{name}.sw{letter}")))
+}
+
+/// the first swap file of `file` left by another session
+pub fn find_swap(file: &Path) -> Option<SwapInfo> {
+    swap_names(file)
+        .filter_map(|path| read_swap(&path).ok())
+        .find(|found| found.pid != std::process::id() || found.host != host_name())
+}
+
+pub fn read_swap(path: &Path) -> Result<SwapInfo> {
+    let text = fs::read_to_string(path)?;
+    let invalid = || {
+        anyhow::Error::from(Error::new(
+            ErrorKind::InvalidData,
+            format!("not a swap file: {}", path.display()),
+        ))
+    };
+    let (header, content) = text.split_once("\n\n").ok_or_else(invalid)?;
+    let mut lines = header.lines();
+    if lines.next() != Some(MAGIC) {
+        return Err(invalid());
+    }
+    let mut found = SwapInfo {
+        path: path.to_path_buf(),
+        pid: 0,
+        host: String::new(),
+        file: PathBuf::new(),
+        content: content.to_string(),
+    };
+    for line in lines {
+        match line.split_once('=').ok_or_else(invalid)? {
+            ("pid", pid) => found.pid = pid.parse().map_err(|_| invalid())?,
+            ("host", host) => found.host = host.to_string(),
+            ("file", file) => found.file = PathBuf::from(file),
+            _ => {}
+        }
+    }
+    Ok(found)
+}
+
+/// swap files in `dir`, for listing them with `-r`
+pub fn swap_files_in(dir: &Path) -> Vec<SwapInfo> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut found: Vec<SwapInfo> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            name.starts_with('.')
+                && extension
+                    .is_some_and(|extension| extension.len() == 3 && extension.starts_with("sw"))
+        })
+        .filter_map(|path| read_swap(&path).ok())
+        .collect();
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    found
+}
+
+/// file listing the directories swap files were written to, one on each
+/// line, as they are kept next to the edited files wherever those are
+pub fn swap_dirs_file() -> PathBuf {
+    recovery_dir().with_file_name("swap-dirs")
+}
+
+/// the directories listed in `dirs_file`
+fn listed_dirs(dirs_file: &Path) -> Vec<PathBuf> {
+    let bytes = fs::read(dirs_file).unwrap_or_default();
+    bytes
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| PathBuf::from(OsStr::from_bytes(line)))
+        .collect()
+}
+
+/// adds the directory of the swap file `path` to `dirs_file`
+fn remember_dir(dirs_file: &Path, path: &Path) -> Result<()> {
+    let dir = fs::canonicalize(path.parent().unwrap_or(Path::new(".")))?;
+    // a name with a line break can not be listed
+    if dir.as_os_str().as_bytes().contains(&b'\n') || listed_dirs(dirs_file).contains(&dir) {
+        return Ok(());
+    }
+    if let Some(parent) = dirs_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut list = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dirs_file)?;
+    list.write_all(dir.as_os_str().as_bytes())?;
+    list.write_all(b"\n")?;
+    Ok(())
+}
+
+/// swap files for `-r`, in the current directory and in the directories of
+/// `dirs_file`, each directory with the swap files found there, the list is
+/// cut down to the directories that still have any
+pub fn recoverable_swaps(dirs_file: &Path) -> Vec<(PathBuf, Vec<SwapInfo>)> {
+    let current = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let listed = listed_dirs(dirs_file);
+    let mut found = vec![(current.clone(), swap_files_in(&current))];
+    for dir in listed.iter().filter(|dir| **dir != current) {
+        let swaps = swap_files_in(dir);
+        if !swaps.is_empty() {
+            found.push((dir.clone(), swaps));
+        }
+    }
+    let kept: Vec<u8> = found
+        .iter()
+        .filter(|(dir, swaps)| !swaps.is_empty() && listed.contains(dir))
+        .flat_map(|(dir, _)| [dir.as_os_str().as_bytes(), b"\n"].concat())
+        .collect();
+    if kept.len() < fs::metadata(dirs_file).map_or(0, |meta| meta.len() as usize) {
+        let _ = fs::write(dirs_file, kept);
+    }
+    found
+}
+
+/// looks for a swap file of the file in `state` and asks what to do with
+/// it, then picks the swap file the session writes to
+pub fn attach_swap(
+    state: &mut State,
+    ask: impl FnOnce(&SwapInfo) -> Result<SwapChoice>,
+) -> Result<()> {
+    let Some(file) = state.file.clone() else {
+        return Ok(());
+    };
+    if !swap_wanted(state) {
+        return Ok(());
+    }
+    if let Some(found) = find_swap(&file) {
+        match ask(&found)? {
+            SwapChoice::Recover => {
+                if found.content != state.content {
+                    state.content = found.content.clone();
+                    state.changes += 1;
+                }
+                // nobody else writes it anymore, the recovered text is kept there
+                if !found.owner_running() {
+                    state.swap = Some(found.path);
+                    return Ok(());
+                }
+            }
+            SwapChoice::ReadOnly => {
+                state.options.readonly = true;
+                return Ok(());
+            }
+            SwapChoice::Edit => {}
+            SwapChoice::Delete => fs::remove_file(&found.path)?,
+            SwapChoice::Quit => {
+                return Err(anyhow::Error::from(Error::other(format!(
+                    "E325: swap file {} exists",
+                    found.path.display()
+                ))))
+            }
+        }
+    }
+    state.swap = swap_names(&file).find(|path| !path.exists());
+    Ok(())
+}
+
+/// asks on the terminal, before it is in raw mode, like vim's ATTENTION
+/// message, without a terminal the file is opened read-only
+pub fn ask(found: &SwapInfo) -> Result<SwapChoice> {
+    if !io::stdin().is_terminal() {
+        return Ok(SwapChoice::ReadOnly);
+    }
+    let running = found.owner_running();
+    let mut stderr = io::stderr();
+    writeln!(stderr, "E325: ATTENTION")?;
+    writeln!(
+        stderr,
+        "Found a swap file by the name \"{}\"",
+        found.path.display()
+    )?;
+    writeln!(
+        stderr,
+        "          owned by process {} on {}{}",
+        found.pid,
+        found.host,
+        if running { " (STILL RUNNING)" } else { "" }
+    )?;
+    writeln!(
+        stderr,
+        "While opening file \"{}\"\n\n\
+         (1) Another program may be editing the same file, be careful not to\n    \
+             end up with two different versions of it.\n\
+         (2) An edit session for this file crashed, recover its changes.\n",
+        found.file.display()
+    )?;
+    let question = if running {
+        "[O]pen Read-Only, (E)dit anyway, (R)ecover, (Q)uit: "
+    } else {
+        "[O]pen Read-Only, (E)dit anyway, (R)ecover, (D)elete it, (Q)uit: "
+    };
+    let mut line = String::new();
+    loop {
+        write!(stderr, "{question}")?;
+        stderr.flush()?;
+        line.clear();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(SwapChoice::Quit);
+        }
+        match line.trim().to_ascii_lowercase().as_str() {
+            "" | "o" => return Ok(SwapChoice::ReadOnly),
+            "e" => return Ok(SwapChoice::Edit),
+            "r" => return Ok(SwapChoice::Recover),
+            "d" if !running => return Ok(SwapChoice::Delete),
+            "q" | "a" => return Ok(SwapChoice::Quit),
+            _ => {}
+        }
+    }
+}
+
+/// the buffer changed since the swap file was written
+pub fn swap_outdated(state: &State) -> bool {
+    state.swap.is_some() && state.options.swapfile && state.swapped_changes != Some(state.changes)
+}
+
+/// changes made since the swap file was written
+pub fn unswapped_changes(state: &State) -> usize {
+    state
+        .changes
+        .saturating_sub(state.swapped_changes.unwrap_or(0))
+}
+
+/// the buffer is kept in a swap file: not after `:set noswapfile`, not for
+/// a large file that can not change and never for an encrypted file, whose
+/// text would be in the swap file unencrypted
+pub fn swap_wanted(state: &State) -> bool {
+    state.options.swapfile && state.large.is_none() && state.key.is_none() && state.locked.is_none()
+}
+
+/// writes the buffer to its swap file when it changed, removing the swap
+/// file when it is not wanted anymore
+pub fn update_swap(state: &mut State) -> Result<()> {
+    update_swap_with(state, &swap_dirs_file())
+}
+
+/// [`update_swap`] noting the directory of a new swap file in `dirs_file`
+pub fn update_swap_with(state: &mut State, dirs_file: &Path) -> Result<()> {
+    if !swap_wanted(state) {
+        remove_swap(state);
+        return Ok(());
+    }
+    let (Some(path), Some(file)) = (&state.swap, &state.file) else {
+        return Ok(());
+    };
+    if state.swapped_changes == Some(state.changes) {
+        return Ok(());
+    }
+    let file = std::path::absolute(file).unwrap_or_else(|_| file.clone());
+    let text = format!(
+        "{MAGIC}\npid={}\nhost={}\nfile={}\n\n{}",
+        std::process::id(),
+        host_name(),
+        file.display(),
+        state.content
+    );
+    // `-r` finds the swap file by its directory
+    if state.swapped_changes.is_none() {
+        remember_dir(dirs_file, path)?;
+    }
+    write_private(path, text.as_bytes())?;
+    state.swapped_changes = Some(state.changes);
+    Ok(())
+}
+
+/// removes the swap file written by this session
+pub fn remove_swap(state: &mut State) {
+    if let Some(path) = &state.swap {
+        if state.swapped_changes.take().is_some() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+pub fn host_name() -> String {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return String::from("localhost");
+    }
+    let end = buffer.iter().position(|&byte| byte == 0).unwrap_or(0);
+    String::from_utf8_lossy(&buffer[..end]).into_owned()
+}
+
+fn process_running(pid: u32) -> bool {
+    // pid 0 would ask about the own process group
+    let Ok(pid @ 1..) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks whether the process exists
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
@@ -1,7 +1,13 @@
 use std::io::stderr;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
-use crossterm::event::{self, poll, KeyCode};
+use crossterm::cursor::{SetCursorStyle, Show};
+use crossterm::event::{
+    self, DisableBracketedPaste, DisableFocusChange, DisableMouseCapture, EnableBracketedPaste,
+    EnableFocusChange, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers, MouseButton,
+    MouseEvent, MouseEventKind,
+};
 use crossterm::execute;
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
@@ -9,130 +15,895 @@ use crossterm::terminal::{
 use ratatui::prelude::CrosstermBackend;
 use ratatui::Terminal;
 
-use crate::editor_mode::EditorMode;
-use crate::editor_state::State;
+use crate::editor_mode::{EditorMode, KeyPrompt};
+use crate::editor_state::{Register, State};
+use crate::events::{AppEvent, Events};
+use crate::hex;
+use crate::keys::{from_notation, to_notation};
+use crate::large;
+use crate::options::Options;
+use crate::recovery::write_recovery;
+use crate::swap::{remove_swap, swap_outdated, swap_wanted, unswapped_changes, update_swap};
 use crate::ui::render_ui;
+use crate::wrap;
+
+/// second click on the same place within this time is a double click
+const DOUBLE_CLICK: Duration = Duration::from_millis(500);
+/// timer writing the swap file once typing pauses for `updatetime`
+const SWAP_TIMER: usize = 0;
+/// timer redrawing the progress of indexing a large file
+const INDEX_TIMER: usize = 1;
 
-pub fn initialize() -> Result<()> {
+pub fn initialize(options: &Options) -> Result<()> {
     enable_raw_mode()?;
-    execute!(stderr(), EnterAlternateScreen)?;
+    // a paste arrives as one event instead of a key press per character
+    execute!(
+        stderr(),
+        EnterAlternateScreen,
+        EnableBracketedPaste,
+        EnableFocusChange
+    )?;
+    if !options.mouse.is_empty() {
+        execute!(stderr(), EnableMouseCapture)?;
+    }
     Ok(())
 }
 
 pub fn quit_app() -> Result<()> {
-    execute!(stderr(), LeaveAlternateScreen)?;
+    execute!(
+        stderr(),
+        SetCursorStyle::DefaultUserShape,
+        Show,
+        DisableMouseCapture,
+        DisableFocusChange,
+        DisableBracketedPaste,
+        LeaveAlternateScreen
+    )?;
     disable_raw_mode()?;
     Ok(())
 }
 
-/// returns result with bool True to quit false to keep running
-fn handle_events(state: &mut State, editor_mode: &mut EditorMode) -> Result<()> {
-    if poll(std::time::Duration::from_millis(250))? {
-        let event = crossterm::event::read()?;
-        if let event::Event::Key(k) = event {
-            // keyboard events
-            if k.kind == event::KeyEventKind::Press {
-                // if app is in idle mode then editor_state can be changed
-                if let EditorMode::Idle(_) = editor_mode {
-                    // going to command mode
-                    if k.code == KeyCode::Char(':') {
-                        editor_mode.enter_command_mode();
-                    // going to insert mode
-                    } else if k.code == KeyCode::Char('i') {
-                        editor_mode.enter_edit_mode(state.file.clone());
-                    } else if k.code == KeyCode::Char('a') {
-                        if state.cursor.location < state.content.len() {
-                            state.cursor.move_char();
+/// gives the terminal back before a panic message is printed, so the message
+/// shows on the normal screen
+pub fn install_panic_hook() {
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = quit_app();
+        hook(info);
+    }));
+}
+
+/// owns the state while the terminal is in raw mode, when dropped after an
+/// error or a panic it restores the terminal and keeps a copy of unsaved changes
+pub struct TerminalGuard {
+    pub state: State,
+    finished: bool,
+}
+
+impl TerminalGuard {
+    pub fn new(state: State) -> Result<Self> {
+        initialize(&state.options)?;
+        Ok(Self {
+            state,
+            finished: false,
+        })
+    }
+
+    /// the editor quit normally, there is nothing to recover
+    pub fn finish(&mut self) {
+        self.finished = true;
+        remove_swap(&mut self.state);
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = quit_app();
+        if !self.finished && self.state.is_modified() {
+            match write_recovery(&self.state) {
+                Ok(path) => eprintln!("unsaved changes were written to {}", path.display()),
+                Err(e) => eprintln!("unsaved changes could not be kept: {e}"),
+            }
+        }
+    }
+}
+
+/// gives the terminal back to the shell and stops like `Ctrl-Z` does in
+/// cooked mode, taking the terminal again once continued
+fn suspend(
+    events: &mut Events,
+    terminal: &mut Terminal<CrosstermBackend<std::io::Stderr>>,
+    state: &State,
+) -> Result<()> {
+    // nothing may read the terminal while the shell owns it
+    events.stop();
+    quit_app()?;
+    // SAFETY: raising a signal has no memory safety requirements
+    unsafe {
+        libc::raise(libc::SIGTSTP);
+    }
+    resume(events, terminal, state)
+}
+
+/// takes the terminal again and starts reading it, the next draw repaints everything
+fn resume(
+    events: &mut Events,
+    terminal: &mut Terminal<CrosstermBackend<std::io::Stderr>>,
+    state: &State,
+) -> Result<()> {
+    events.stop();
+    initialize(&state.options)?;
+    terminal.clear()?;
+    listen(events, state)
+}
+
+/// starts the threads feeding the main loop
+fn listen(events: &mut Events, state: &State) -> Result<()> {
+    events.listen_terminal()?;
+    if let Some(file) = &state.file {
+        events.watch_file(file.clone());
+    }
+    Ok(())
+}
+
+/// handles an event of the main loop, returning whether the screen needs drawing
+pub fn handle_event(
+    state: &mut State,
+    editor_mode: &mut EditorMode,
+    event: AppEvent,
+) -> Result<bool> {
+    match event {
+        AppEvent::Terminal(event) => handle_terminal_event(state, editor_mode, event)?,
+        AppEvent::FileChanged => return Ok(warn_if_changed(state, editor_mode)),
+        AppEvent::JobOutput(_, line) => {
+            if let EditorMode::Idle(_) = editor_mode {
+                editor_mode.enter_idle_mode(Some(line));
+            }
+        }
+        AppEvent::JobExited(id, code) => {
+            if let EditorMode::Idle(_) = editor_mode {
+                let code = code.map_or(String::from("a signal"), |code| code.to_string());
+                editor_mode.enter_idle_mode(Some(format!("job {id} exited with {code}")));
+            }
+        }
+        AppEvent::Timer(SWAP_TIMER) => return Ok(write_swap(state, editor_mode)),
+        AppEvent::Timer(INDEX_TIMER) => return Ok(true),
+        AppEvent::Timer(_) => return Ok(false),
+        // the full redraw after being continued is done by the main loop
+        AppEvent::Continued => {}
+    }
+    Ok(true)
+}
+
+fn handle_terminal_event(
+    state: &mut State,
+    editor_mode: &mut EditorMode,
+    event: Event,
+) -> Result<()> {
+    match event {
+        Event::Key(k) => {
+            if let Some((_, keys)) = &mut state.recording_macro {
+                // a key typed for encryption never ends up in a register
+                if !matches!(editor_mode, EditorMode::Key(_)) {
+                    keys.push(k);
+                }
+            }
+            execute_keys(state, editor_mode, &[k])?;
+        }
+        Event::Mouse(mouse) => handle_mouse(state, editor_mode, mouse),
+        Event::Paste(text) => paste(state, editor_mode, &text),
+        Event::Resize(columns, rows) => state.viewport.reflow(columns, rows),
+        Event::FocusGained => {
+            warn_if_changed(state, editor_mode);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// writes the swap file, returning whether an error is shown
+fn write_swap(state: &mut State, editor_mode: &mut EditorMode) -> bool {
+    match update_swap(state) {
+        Ok(()) => false,
+        Err(e) => {
+            editor_mode.enter_idle_mode(Some(format!("E297: Write error in swap file: {e}")));
+            true
+        }
+    }
+}
+
+/// keeps the swap file up to date, right away after `updatecount` changes
+/// and otherwise once no event came for `updatetime`
+fn schedule_swap(state: &mut State, editor_mode: &mut EditorMode, events: &mut Events) -> bool {
+    if !swap_wanted(state) {
+        remove_swap(state);
+        return false;
+    }
+    if !swap_outdated(state) {
+        return false;
+    }
+    let count = state.options.updatecount;
+    if count > 0 && unswapped_changes(state) >= count {
+        events.stop_timer(SWAP_TIMER);
+        return write_swap(state, editor_mode);
+    }
+    let delay = Duration::from_millis(state.options.updatetime as u64);
+    events.set_timer(SWAP_TIMER, delay, false);
+    false
+}
+
+/// shows a warning in idle mode when another program wrote the file, with
+/// `autoread` a buffer without changes is read again instead
+fn warn_if_changed(state: &mut State, editor_mode: &mut EditorMode) -> bool {
+    if !state.changed_on_disk() {
+        return false;
+    }
+    let exists = state.file.as_ref().is_some_and(|file| file.exists());
+    let message = if state.options.autoread && exists && !state.is_modified() {
+        match state.reload() {
+            Ok(()) => format!("\"{}\" reloaded", state.file_name().unwrap_or_default()),
+            Err(e) => format!("E321: Could not reload: {e}"),
+        }
+    } else {
+        String::from("W11: Warning: File has changed since editing started")
+    };
+    // a selection may point past the reloaded text
+    if let EditorMode::Idle(_) | EditorMode::Visual(_) = editor_mode {
+        editor_mode.enter_idle_mode(Some(message));
+    }
+    true
+}
+
+/// clicks move the cursor, a drag selects, a double click selects a word
+/// and the wheel scrolls, in the modes named by the `mouse` option
+pub fn handle_mouse(state: &mut State, editor_mode: &mut EditorMode, mouse: MouseEvent) {
+    let mode = match editor_mode {
+        EditorMode::Idle(_) => 'n',
+        EditorMode::Visual(_) => 'v',
+        EditorMode::Edit(_) | EditorMode::Replace(_) => 'i',
+        EditorMode::Command(_) | EditorMode::Key(_) => 'c',
+    };
+    if !state.options.mouse_in(mode) {
+        return;
+    }
+    // a large file has no text to click on, only the wheel moves through it
+    if let Some(large) = &mut state.large {
+        match mouse.kind {
+            MouseEventKind::ScrollDown => large.page(3),
+            MouseEventKind::ScrollUp => large.page(-3),
+            _ => {}
+        }
+        return;
+    }
+    // the hex view only overwrites bytes, a click or drag must not select text
+    if state.options.binary {
+        match mouse.kind {
+            MouseEventKind::ScrollDown => hex::scroll(state, 3),
+            MouseEventKind::ScrollUp => hex::scroll(state, -3),
+            _ => {}
+        }
+        return;
+    }
+    let location = wrap::location_on_screen(state, mouse.column, mouse.row);
+    match (mouse.kind, &editor_mode) {
+        (MouseEventKind::ScrollDown | MouseEventKind::ScrollUp, _) => {
+            state.count = Some(3);
+            scroll_view(
+                state,
+                if mouse.kind == MouseEventKind::ScrollDown {
+                    'e'
+                } else {
+                    'y'
+                },
+            );
+        }
+        (_, EditorMode::Command(_) | EditorMode::Key(_)) => {}
+        (MouseEventKind::Down(MouseButton::Left), EditorMode::Edit(_) | EditorMode::Replace(_)) => {
+            state.cursor.location = location;
+        }
+        (MouseEventKind::Down(MouseButton::Left), _) => {
+            let double = matches!(state.last_click,
+                Some((time, at)) if at == location && time.elapsed() < DOUBLE_CLICK);
+            state.last_click = Some((Instant::now(), location));
+            let (start, end) = state.word_at(location);
+            if double && start < end {
+                editor_mode.enter_visual_mode(start, false);
+                // the selection includes the character under the cursor
+                let last = state.content[..end].chars().next_back().map(char::len_utf8);
+                state.cursor.location = end - last.unwrap_or(0);
+            } else {
+                if let EditorMode::Visual(_) = editor_mode {
+                    state.visual_marks = editor_mode.selection(state);
+                    editor_mode.enter_idle_mode(None);
+                }
+                state.cursor.location = location;
+            }
+        }
+        (MouseEventKind::Drag(MouseButton::Left), EditorMode::Idle(_) | EditorMode::Visual(_)) => {
+            if let EditorMode::Idle(_) = editor_mode {
+                editor_mode.enter_visual_mode(state.cursor.location, false);
+            }
+            state.cursor.location = location;
+        }
+        _ => {}
+    }
+}
+
+/// bracketed paste inserts the text at once, in idle mode it is put before the cursor
+pub fn paste(state: &mut State, editor_mode: &mut EditorMode, text: &str) {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    match editor_mode {
+        EditorMode::Command(command) | EditorMode::Key(KeyPrompt { typed: command, .. }) => {
+            command.extend(text.chars().filter(|ch| *ch != '\n'));
+        }
+        // the hex view only overwrites bytes and a large file nothing
+        _ if state.options.binary || state.large.is_some() => {}
+        EditorMode::Edit(_) | EditorMode::Replace(_) => {
+            let replace = matches!(editor_mode, EditorMode::Replace(_));
+            // kept as typed keys so `.` repeats the insert with the pasted
+            // text, in replace mode it overwrites like typing it does
+            for ch in text.chars() {
+                let key = KeyEvent::from(match ch {
+                    '\n' => KeyCode::Enter,
+                    ch => KeyCode::Char(ch),
+                });
+                state.record_key(key);
+                if replace {
+                    insert_key(state, key.code, true);
+                }
+            }
+            if !replace {
+                state.insert_text(&text);
+            }
+        }
+        EditorMode::Idle(_) => state.insert_text(&text),
+        EditorMode::Visual(_) => {}
+    }
+}
+
+/// handles `keys` and everything they push to the typeahead, like a played macro
+pub fn execute_keys(
+    state: &mut State,
+    editor_mode: &mut EditorMode,
+    keys: &[KeyEvent],
+) -> Result<()> {
+    let outer = std::mem::replace(&mut state.typeahead, keys.iter().copied().collect());
+    while let Some(k) = state.typeahead.pop_front() {
+        handle_key(state, editor_mode, k)?;
+    }
+    state.typeahead = outer;
+    Ok(())
+}
+
+/// applies a single key press, keys can also be injected from commands like `:normal`
+pub fn handle_key(state: &mut State, editor_mode: &mut EditorMode, k: KeyEvent) -> Result<()> {
+    let pressed = k.kind == event::KeyEventKind::Press;
+    if pressed && large::handle_key(state, editor_mode, k) {
+        return Ok(());
+    }
+    if pressed && state.options.binary && hex::handle_key(state, editor_mode, k) {
+        return Ok(());
+    }
+    if k.kind == event::KeyEventKind::Press {
+        // if app is in idle mode then editor_state can be changed
+        if let EditorMode::Idle(_) = editor_mode {
+            handle_idle_key(state, editor_mode, k)?;
+        // selecting text in visual mode
+        } else if let EditorMode::Visual(selection) = editor_mode {
+            let selection = *selection;
+            match k.code {
+                KeyCode::Char(ch @ ('h' | 'j' | 'k' | 'l' | 'w')) => move_cursor(state, ch),
+                KeyCode::Char('v') if selection.linewise => {
+                    editor_mode.enter_visual_mode(selection.anchor, false)
+                }
+                KeyCode::Char('V') if !selection.linewise => {
+                    editor_mode.enter_visual_mode(selection.anchor, true)
+                }
+                KeyCode::Char('v' | 'V') | KeyCode::Esc => {
+                    state.visual_marks = editor_mode.selection(state);
+                    editor_mode.enter_idle_mode(None);
+                }
+                KeyCode::Char(':') => {
+                    state.visual_marks = editor_mode.selection(state);
+                    editor_mode.enter_command_mode_on_selection();
+                }
+                KeyCode::Char(ch @ ('d' | 'x' | 'y')) => {
+                    if let Some((start, end)) = editor_mode.selection(state) {
+                        state.visual_marks = Some((start, end));
+                        let (first, last) = (state.line_of(start), state.line_of(end));
+                        if selection.linewise {
+                            state.set_register(None, state.lines_text(first, last), true);
+                        } else {
+                            state.set_register(None, state.content[start..end].to_string(), false);
                         }
-                        editor_mode.enter_edit_mode(state.file.clone());
-                    } else if k.code == KeyCode::Char('o') {
-                        state.next_line_insert();
-                        editor_mode.enter_edit_mode(state.file.clone());
-                    } else if k.code == KeyCode::Char('O') {
-                        state.above_line_insert();
-                        editor_mode.enter_edit_mode(state.file.clone());
-                    }
-                    // navigation in idle mode
-                    else if k.code == KeyCode::Char('l') {
-                        state.move_cursor_ahead();
-                    } else if k.code == KeyCode::Char('h') {
-                        state.move_cursor_behind();
-                    } else if k.code == KeyCode::Char('k') {
-                        state.move_cursor_up();
-                    } else if k.code == KeyCode::Char('j') {
-                        state.move_cursor_down();
-                    } else if k.code == KeyCode::Char('w') {
-                        state.move_by_a_word();
-                    }
-                    // remove content
-                    else if k.code == KeyCode::Char('x') {
-                        state.remove_from_edit()
-                    } else if k.code == KeyCode::Char('d') {
-                        if let Some(c) = &state.stacked_command {
-                            if c == "d" {
-                                state.delete_line();
-                                state.stacked_command = None;
-                            } else {
-                                state.stacked_command = None;
-                            }
+                        if ch == 'y' {
+                            state.cursor.location = start;
+                        } else if selection.linewise {
+                            state.delete_lines(first, last);
                         } else {
-                            state.stacked_command = Some(String::from('d'));
+                            state.delete_range(start, end);
                         }
                     }
-                // app is not in idle mode
-                } else if k.code == KeyCode::Esc {
                     editor_mode.enter_idle_mode(None);
-                // Some input in Edit or Command mode
-                } else {
-                    // command mode
-                    if let EditorMode::Command(_) = editor_mode {
-                        match k.code {
-                            KeyCode::Char(value) => {
-                                editor_mode.update_command(value);
-                            }
-                            KeyCode::Backspace => {
-                                editor_mode.remove_from_command();
-                            }
-                            KeyCode::Enter => match editor_mode.apply_command(state) {
-                                Ok(message) => editor_mode.enter_idle_mode(Some(message)),
-                                Err(m) => editor_mode.enter_idle_mode(Some(m.to_string())),
-                            },
-                            _ => {}
-                        }
-                    // editor mode
-                    } else {
-                        match k.code {
-                            KeyCode::Char(value) => {
-                                state.update_edit(value);
-                            }
-                            KeyCode::Backspace => {
-                                state.remove_from_edit();
-                            }
-                            KeyCode::Enter => state.add_newline_edit(),
-                            _ => {}
+                }
+                _ => {}
+            }
+        // app is not in idle mode
+        } else if k.code == KeyCode::Esc {
+            if let EditorMode::Edit(_) | EditorMode::Replace(_) = editor_mode {
+                state.record_key(k);
+                finish_insert(state, matches!(editor_mode, EditorMode::Replace(_)));
+            }
+            editor_mode.enter_idle_mode(None);
+        // Some input in Edit or Command mode
+        } else {
+            // command mode
+            if let EditorMode::Command(_) = editor_mode {
+                match k.code {
+                    KeyCode::Char(value) => {
+                        editor_mode.update_command(value);
+                    }
+                    KeyCode::Backspace => {
+                        editor_mode.remove_from_command();
+                    }
+                    KeyCode::Enter => match editor_mode.apply_command(state) {
+                        // `:X` goes on to ask for the key
+                        Ok(_) if matches!(editor_mode, EditorMode::Key(_)) => {}
+                        Ok(message) => editor_mode.enter_idle_mode(Some(message)),
+                        Err(m) => editor_mode.enter_idle_mode(Some(m.to_string())),
+                    },
+                    _ => {}
+                }
+            // typing a key, which is not recorded for `.`
+            } else if let EditorMode::Key(_) = editor_mode {
+                match k.code {
+                    KeyCode::Char(value) => editor_mode.update_command(value),
+                    KeyCode::Backspace => editor_mode.remove_from_command(),
+                    KeyCode::Enter => match editor_mode.apply_key(state) {
+                        Ok(Some(message)) => editor_mode.enter_idle_mode(Some(message)),
+                        Ok(None) => {}
+                        Err(m) => editor_mode.enter_idle_mode(Some(m.to_string())),
+                    },
+                    _ => {}
+                }
+            // editor mode
+            } else {
+                state.record_key(k);
+                insert_key(state, k.code, matches!(editor_mode, EditorMode::Replace(_)));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_idle_key(state: &mut State, editor_mode: &mut EditorMode, k: KeyEvent) -> Result<()> {
+    // a count before or inside a command, `0` alone is not a count
+    if k.modifiers.contains(KeyModifiers::CONTROL) {
+        match k.code {
+            KeyCode::Char('z' | 'Z') => state.suspend_requested = true,
+            KeyCode::Char(ch) => scroll_view(state, ch.to_ascii_lowercase()),
+            _ => {}
+        }
+        state.stacked_command = None;
+        state.pending_register = None;
+        state.count = None;
+        return Ok(());
+    }
+    let wants_register = matches!(state.stacked_command.as_deref(), Some("q" | "@" | "\""));
+    if let KeyCode::Char(digit @ '0'..='9') = k.code {
+        if !wants_register && (digit != '0' || state.count.is_some()) {
+            let digit = digit.to_digit(10).unwrap_or_default() as usize;
+            state.count = Some(state.count.unwrap_or(0).saturating_mul(10) + digit);
+            return Ok(());
+        }
+    }
+    if state.stacked_command.is_none() && state.pending_register.is_none() {
+        if k.code == KeyCode::Char('.') {
+            return repeat_last_change(state, editor_mode);
+        }
+        state.begin_change();
+    }
+    state.record_key(k);
+
+    let mut selected_register = false;
+    if let Some(c) = state.stacked_command.take() {
+        if let KeyCode::Char(ch) = k.code {
+            match c.as_str() {
+                "d" | "y" => apply_operator(state, c == "d", ch),
+                "z" if matches!(ch, 'h' | 'l' | 's' | 'e') => scroll_sideways(state, ch),
+                "z" => {
+                    let line = state.cursor_line();
+                    let scrolloff = state.options.scrolloff;
+                    state.viewport.align(line, ch, scrolloff);
+                }
+                "g" => match ch {
+                    'j' | 'k' => {
+                        for _ in 0..state.count.take().unwrap_or(1) {
+                            state.move_display_line(ch == 'j');
                         }
                     }
+                    '0' | '$' => state.move_to_display_edge(ch == '$'),
+                    _ => {}
+                },
+                "\"" if ch.is_ascii_alphanumeric() || ch == '"' => {
+                    state.pending_register = Some(ch);
+                    selected_register = true;
                 }
+                "q" => start_recording(state, editor_mode, ch),
+                "Z" => match ch {
+                    'Z' => {
+                        if let Err(e) = state.write_and_quit(false) {
+                            editor_mode.enter_idle_mode(Some(e.to_string()));
+                        }
+                    }
+                    'Q' => state.end_program(),
+                    _ => {}
+                },
+                "@" => play_macro(state, ch),
+                _ => {}
+            }
+        }
+    }
+    // going to command mode
+    else if k.code == KeyCode::Char(':') {
+        editor_mode.enter_command_mode();
+    // going to insert mode
+    } else if let KeyCode::Char(ch @ ('i' | 'I' | 'a' | 'A' | 'o' | 'O')) = k.code {
+        match ch {
+            'I' => state.insert_at_line_start(),
+            'a' => state.move_cursor_ahead(),
+            'A' => state.append_at_line_end(),
+            'o' => state.next_line_insert(),
+            'O' => state.above_line_insert(),
+            _ => {}
+        }
+        // the count repeats the inserted text when the insert ends
+        let count = state.count.take();
+        state.set_change_count(count);
+        editor_mode.enter_edit_mode(state.file.clone());
+    } else if k.code == KeyCode::Char('R') {
+        let count = state.count.take();
+        state.set_change_count(count);
+        state.replaced.clear();
+        editor_mode.enter_replace_mode(state.file.clone());
+    }
+    // going to visual mode
+    else if k.code == KeyCode::Char('v') {
+        editor_mode.enter_visual_mode(state.cursor.location, false);
+    } else if k.code == KeyCode::Char('V') {
+        editor_mode.enter_visual_mode(state.cursor.location, true);
+    }
+    // navigation in idle mode
+    else if let KeyCode::Char(ch @ ('h' | 'j' | 'k' | 'l' | 'w')) = k.code {
+        let start = state.cursor.location;
+        for _ in 0..state.count.unwrap_or(1) {
+            move_cursor(state, ch);
+        }
+        if state.cursor.location == start {
+            // like vim, a motion that fails stops a running macro
+            state.typeahead.clear();
+        }
+    }
+    // macros
+    else if k.code == KeyCode::Char('q') {
+        if let Some((register, mut keys)) = state.recording_macro.take() {
+            // the `q` ending the recording is not part of the macro
+            keys.pop();
+            let lower = register.to_ascii_lowercase();
+            let mut text = match state.registers.get(&lower) {
+                Some(existing) if register.is_ascii_uppercase() => existing.text.clone(),
+                _ => String::new(),
+            };
+            text.push_str(&to_notation(&keys));
+            state.registers.insert(
+                lower,
+                Register {
+                    text,
+                    linewise: false,
+                },
+            );
+            editor_mode.enter_idle_mode(None);
+        } else {
+            state.stacked_command = Some(String::from('q'));
+        }
+    } else if k.code == KeyCode::Char('@') {
+        state.stacked_command = Some(String::from('@'));
+    }
+    // remove content
+    else if k.code == KeyCode::Char('x') {
+        let count = state.count.take();
+        for _ in 0..count.unwrap_or(1) {
+            state.remove_from_edit();
+        }
+        state.complete_change(count);
+    } else if let KeyCode::Char(ch @ ('d' | 'y' | '"' | 'z' | 'g' | 'Z')) = k.code {
+        state.stacked_command = Some(String::from(ch));
+    }
+    // put text from a register
+    else if let KeyCode::Char(ch @ ('p' | 'P')) = k.code {
+        let count = state.count.take();
+        if let Some(register) = state.register(state.pending_register).cloned() {
+            for _ in 0..count.unwrap_or(1) {
+                state.put(&register, ch == 'P');
+            }
+            state.complete_change(count);
+        }
+    }
+
+    // anything that is neither a pending command nor a change is forgotten,
+    // a register only applies to the command right after it
+    if state.stacked_command.is_none() && !selected_register {
+        state.pending_register = None;
+        if let EditorMode::Idle(_) = editor_mode {
+            state.count = None;
+            state.discard_change();
+        }
+    }
+    Ok(())
+}
+
+/// `q` followed by a register, an uppercase register appends to the lowercase one
+fn start_recording(state: &mut State, editor_mode: &mut EditorMode, register: char) {
+    if !register.is_ascii_alphanumeric() {
+        return;
+    }
+    state.recording_macro = Some((register, Vec::new()));
+    editor_mode.enter_idle_mode(Some(format!(
+        "recording @{}",
+        register.to_ascii_lowercase()
+    )));
+}
+
+/// `@` followed by a register, `@@` plays the last one again and `@:` repeats the last command
+fn play_macro(state: &mut State, register: char) {
+    let register = if register == '@' {
+        match state.last_macro {
+            Some(register) => register,
+            None => return,
+        }
+    } else {
+        register.to_ascii_lowercase()
+    };
+    let keys: Vec<KeyEvent> = if register == ':' {
+        let Some(command) = &state.last_command else {
+            return;
+        };
+        std::iter::once(':')
+            .chain(command.chars())
+            .map(|ch| KeyEvent::from(KeyCode::Char(ch)))
+            .chain(std::iter::once(KeyEvent::from(KeyCode::Enter)))
+            .collect()
+    } else {
+        match state.registers.get(&register) {
+            Some(macro_register) => from_notation(&macro_register.text),
+            None => return,
+        }
+    };
+    state.last_macro = Some(register);
+    // played before anything already waiting, so a macro can call itself
+    for _ in 0..state.count.take().unwrap_or(1) {
+        for key in keys.iter().rev() {
+            state.typeahead.push_front(*key);
+        }
+    }
+}
+
+/// `d` or `y` followed by a motion, doubling the operator works on whole lines
+fn apply_operator(state: &mut State, delete: bool, motion: char) {
+    let count = state.count.take();
+    let times = count.unwrap_or(1);
+    let register = state.pending_register.take();
+    let line = state.cursor_line();
+    let last_line = state.line_count() - 1;
+    let lines = match motion {
+        'd' if delete => (line, (line + times - 1).min(last_line)),
+        'y' if !delete => (line, (line + times - 1).min(last_line)),
+        'j' => (line, (line + times).min(last_line)),
+        'k' => (line.saturating_sub(times), line),
+        'h' | 'l' | 'w' => {
+            let start = state.cursor.location;
+            for _ in 0..times {
+                move_cursor(state, motion);
             }
+            let end = state.cursor.location;
+            let (start, end) = (start.min(end), start.max(end));
+            state.set_register(register, state.content[start..end].to_string(), false);
+            if delete {
+                state.delete_range(start, end);
+                state.complete_change(count);
+            } else {
+                state.cursor.location = start;
+            }
+            return;
         }
+        _ => return,
+    };
+    let (first, last) = lines;
+    state.set_register(register, state.lines_text(first, last), true);
+    if delete {
+        state.delete_lines(first, last);
+        state.complete_change(count);
+    } else if first < line {
+        state.move_to_line(first);
+    }
+}
+
+/// `Ctrl-E`/`Ctrl-Y` scroll by lines, `Ctrl-D`/`Ctrl-U` by half a page and
+/// `Ctrl-F`/`Ctrl-B` by pages, the cursor stays on screen
+fn scroll_view(state: &mut State, key: char) {
+    let count = state.count.take();
+    let rows = state.viewport.height.max(1);
+    let line_count = state.line_count();
+    let scrolloff = state.options.scrolloff;
+    let line = state.cursor_line();
+    let lines = match key {
+        'e' | 'y' => count.unwrap_or(1),
+        'd' | 'u' => count.unwrap_or(rows / 2).max(1),
+        'f' | 'b' => count.unwrap_or(1) * rows.saturating_sub(2).max(1),
+        _ => return,
+    } as isize;
+    let lines = if matches!(key, 'e' | 'd' | 'f') {
+        lines
+    } else {
+        -lines
+    };
+    state.viewport.scroll(lines, line_count);
+    let target = if matches!(key, 'd' | 'u') {
+        // half pages move the cursor as far as the text
+        line.saturating_add_signed(lines).min(line_count - 1)
+    } else {
+        line
+    };
+    let target = state.viewport.clamp_line(target, line_count, scrolloff);
+    if matches!(key, 'd' | 'u' | 'f' | 'b') && state.options.startofline {
+        state.move_to_first_non_blank(target);
+    } else if target != line {
+        state.set_cursor_line(target);
+    }
+}
+
+/// `zh`/`zl` scroll a count of columns and `zs`/`ze` put the cursor at the
+/// start or end of the screen, only while lines are not wrapped
+fn scroll_sideways(state: &mut State, key: char) {
+    let count = state.count.take().unwrap_or(1);
+    if state.options.wrap {
+        return;
+    }
+    let columns = state.viewport.width.max(1);
+    let row = wrap::line_rows(state, state.cursor_line())[0];
+    let column = wrap::column_of(state, &row, state.cursor.location);
+    let viewport = &mut state.viewport;
+    viewport.left = match key {
+        'h' => viewport.left.saturating_sub(count),
+        'l' => viewport.left + count,
+        's' => column,
+        'e' => (column + 1).saturating_sub(columns),
+        _ => viewport.left,
+    };
+    // the cursor moves along when it would leave the screen
+    let target = column.clamp(viewport.left, viewport.left + columns - 1);
+    if target != column {
+        state.cursor.location = wrap::location_at(state, &row, target, true);
+    }
+}
+
+/// `.` plays the keys of the last change again, a new count replaces the recorded one
+fn repeat_last_change(state: &mut State, editor_mode: &mut EditorMode) -> Result<()> {
+    let Some(change) = state.last_change.clone() else {
+        state.count = None;
+        return Ok(());
+    };
+    state.count = state.count.or(change.count);
+    for key in change.keys {
+        handle_key(state, editor_mode, key)?;
     }
     Ok(())
 }
 
+fn insert_key(state: &mut State, code: KeyCode, replace: bool) {
+    match code {
+        KeyCode::Char(value) if replace => state.replace_edit(value),
+        KeyCode::Char(value) => {
+            state.update_edit(value);
+        }
+        KeyCode::Backspace if replace => state.unreplace_edit(),
+        KeyCode::Backspace => {
+            state.remove_from_edit();
+        }
+        KeyCode::Enter => {
+            state.add_newline_edit();
+            if replace {
+                state.replaced.push(None);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// ends an insert or replace session, typing its text again for a count
+fn finish_insert(state: &mut State, replace: bool) {
+    let Some(change) = state.recording_change.clone() else {
+        return;
+    };
+    if let Some((first, rest)) = change.keys.split_first() {
+        for _ in 1..change.count.unwrap_or(1) {
+            match first.code {
+                KeyCode::Char('o') => state.next_line_insert(),
+                KeyCode::Char('O') => state.above_line_insert(),
+                _ => {}
+            }
+            for key in rest {
+                insert_key(state, key.code, replace);
+            }
+        }
+    }
+    state.complete_change(change.count);
+}
+
+fn move_cursor(state: &mut State, motion: char) {
+    match motion {
+        'l' => state.move_cursor_ahead(),
+        'h' => state.move_cursor_behind(),
+        'k' => state.move_cursor_up(),
+        'j' => state.move_cursor_down(),
+        'w' => state.move_by_a_word(),
+        _ => {}
+    }
+}
+
 pub fn run_event_loop(
-    mut state: State,
+    state: &mut State,
     mut terminal: Terminal<CrosstermBackend<std::io::Stderr>>,
     mut editor_state: EditorMode,
 ) -> Result<()> {
-    loop {
-        if !state.running {
-            break;
+    let mut events = Events::new();
+    listen(&mut events, state)?;
+    let mut mouse_captured = !state.options.mouse.is_empty();
+    // the swap file shows other sessions that the file is being edited
+    write_swap(state, &mut editor_state);
+    render_ui(&mut terminal, &mut editor_state, state)?;
+    let mut dirty = false;
+    let mut indexing = false;
+    while state.running {
+        let event = events.wait()?;
+        // stopped and continued from outside, the terminal may have been changed
+        if let AppEvent::Continued = event {
+            resume(&mut events, &mut terminal, state)?;
         }
-        // event management
-        handle_events(&mut state, &mut editor_state)?;
+        dirty |= handle_event(state, &mut editor_state, event)?;
+        if std::mem::take(&mut state.suspend_requested) {
+            suspend(&mut events, &mut terminal, state)?;
+            dirty = true;
+        }
+        dirty |= schedule_swap(state, &mut editor_state, &mut events);
 
-        // user interface
-        render_ui(&mut terminal, &mut editor_state, &state)?;
+        // the status line shows how far the lines of a large file are indexed
+        let unindexed = state.large.as_ref().is_some_and(|large| !large.indexed());
+        if unindexed != indexing {
+            indexing = unindexed;
+            if indexing {
+                events.set_timer(INDEX_TIMER, Duration::from_millis(200), true);
+            } else {
+                events.stop_timer(INDEX_TIMER);
+                dirty = true;
+            }
+        }
+
+        // `:set mouse` takes the mouse from the terminal or gives it back
+        if state.options.mouse.is_empty() == mouse_captured {
+            mouse_captured = !mouse_captured;
+            if mouse_captured {
+                execute!(stderr(), EnableMouseCapture)?;
+            } else {
+                execute!(stderr(), DisableMouseCapture)?;
+            }
+        }
+
+        // drawing waits until every queued event, like a fast typist's keys, is handled
+        if dirty && state.running && !events.pending() {
+            render_ui(&mut terminal, &mut editor_state, state)?;
+            dirty = false;
+        }
     }
     Ok(())
 }
@@ -1,58 +1,350 @@
 use anyhow::Result;
+use crossterm::{cursor::SetCursorStyle, execute};
 use ratatui::{
-    prelude::{Constraint, CrosstermBackend, Layout},
-    style::{Color, Stylize},
-    widgets::{Block, Borders, Paragraph},
+    prelude::{Constraint, CrosstermBackend, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::Paragraph,
     Frame, Terminal,
 };
 
-use crate::{editor_mode::EditorMode, editor_state::State};
+use unicode_width::UnicodeWidthStr;
 
-fn layout_layer(frame: &Frame) -> std::rc::Rc<[ratatui::prelude::Rect]> {
-    Layout::default()
+use crate::{
+    editor_mode::EditorMode,
+    editor_state::State,
+    hex, large,
+    options::SignColumn,
+    statusline,
+    wrap::{self, DisplayRow},
+};
+
+/// screen areas from top to bottom, optional lines are only there when enabled
+struct Areas {
+    tabline: Option<Rect>,
+    winbar: Option<Rect>,
+    text: Rect,
+    status: Rect,
+    command: Rect,
+}
+
+fn layout_layer(frame: &Frame, state: &State) -> Areas {
+    let options = &state.options;
+    let tabline = options.showtabline == 2;
+    let winbar = !options.winbar.is_empty();
+    let mut constraints = Vec::new();
+    if tabline {
+        constraints.push(Constraint::Length(1));
+    }
+    if winbar {
+        constraints.push(Constraint::Length(1));
+    }
+    constraints.extend([
+        Constraint::Min(1),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ]);
+    let layout = Layout::default()
         .direction(ratatui::prelude::Direction::Vertical)
-        .constraints([
-            Constraint::Percentage(15),
-            Constraint::Percentage(70),
-            Constraint::Min(1),
-        ])
-        .split(frame.size())
+        .constraints(constraints)
+        .split(frame.size());
+    let mut areas = layout.iter().copied();
+    Areas {
+        tabline: tabline.then(|| areas.next()).flatten(),
+        winbar: winbar.then(|| areas.next()).flatten(),
+        text: areas.next().unwrap_or_default(),
+        status: areas.next().unwrap_or_default(),
+        command: areas.next().unwrap_or_default(),
+    }
+}
+
+/// columns taken by the sign column and the line numbers
+fn gutter_widths(state: &State) -> (u16, u16) {
+    let options = &state.options;
+    let signs = match options.signcolumn {
+        SignColumn::Yes => 2,
+        SignColumn::Auto if !state.signs.is_empty() => 2,
+        _ => 0,
+    };
+    let numbers = if options.number || options.relativenumber {
+        let digits = state.line_count().to_string().len();
+        // one column is kept free between the numbers and the text
+        (digits + 1).max(options.numberwidth) as u16
+    } else {
+        0
+    };
+    (signs, numbers)
+}
+
+/// signs and line numbers for the visible rows, rows continuing a wrapped
+/// line stay empty
+fn gutter(state: &State, rows: &[DisplayRow], sign_width: u16, number_width: u16) -> Text<'static> {
+    let options = &state.options;
+    let cursor_line = state.cursor_line();
+    let mut lines = Vec::new();
+    for row in rows {
+        let line = row.line;
+        let mut spans = Vec::new();
+        if row.continued {
+            spans.push(Span::raw(" ".repeat((sign_width + number_width) as usize)));
+            lines.push(Line::from(spans));
+            continue;
+        }
+        if sign_width > 0 {
+            spans.push(match state.signs.at(line) {
+                Some(sign) => Span::styled(
+                    format!("{:<2.2}", sign.text),
+                    Style::default().fg(sign.color),
+                ),
+                None => Span::raw("  "),
+            });
+        }
+        if number_width > 0 {
+            let relative = line.abs_diff(cursor_line);
+            let (number, current) = match (options.number, options.relativenumber) {
+                // hybrid numbers show the cursor line absolute and left aligned
+                (true, true) if relative == 0 => {
+                    (format!("{:<1$}", line + 1, number_width as usize - 1), true)
+                }
+                (_, true) => (
+                    format!("{:>1$}", relative, number_width as usize - 1),
+                    relative == 0,
+                ),
+                _ => (
+                    format!("{:>1$}", line + 1, number_width as usize - 1),
+                    relative == 0,
+                ),
+            };
+            let style = if current {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default().fg(Color::DarkGray)
+            };
+            spans.push(Span::styled(format!("{number} "), style));
+        }
+        lines.push(Line::from(spans));
+    }
+    Text::from(lines)
+}
+
+/// `text` starting at byte `offset` of the content, with the selected bytes
+/// shown reversed
+fn highlight_selection(
+    text: &str,
+    offset: usize,
+    selection: Option<(usize, usize)>,
+) -> Vec<Span<'_>> {
+    let Some((start, end)) = selection else {
+        return vec![Span::raw(text)];
+    };
+    let from = start.clamp(offset, offset + text.len()) - offset;
+    let to = end.clamp(offset, offset + text.len()) - offset;
+    vec![
+        Span::raw(&text[..from]),
+        Span::styled(
+            &text[from..to],
+            Style::default().add_modifier(Modifier::REVERSED),
+        ),
+        Span::raw(&text[to..]),
+    ]
+}
+
+/// the screen rows of the text area, wrapped rows start with `showbreak` and
+/// the indent, unwrapped lines are cut to the columns scrolled to
+fn text_rows<'a>(
+    state: &'a State,
+    rows: &[DisplayRow],
+    selection: Option<(usize, usize)>,
+) -> Text<'a> {
+    let options = &state.options;
+    let mut lines = Vec::new();
+    for row in rows {
+        let mut spans = Vec::new();
+        let (mut start, mut end) = (row.start, row.end);
+        if row.continued && row.prefix > 0 {
+            let showbreak = display_width(&options.showbreak);
+            spans.push(Span::raw(" ".repeat(row.prefix.saturating_sub(showbreak))));
+            spans.push(Span::styled(
+                options.showbreak.as_str(),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        if !options.wrap {
+            let left = state.viewport.left;
+            let (from, to) = wrap::columns(
+                &state.content[start..end],
+                left,
+                left + state.viewport.width,
+            );
+            (start, end) = (start + from, start + to);
+        }
+        spans.extend(highlight_selection(
+            &state.content[start..end],
+            start,
+            selection,
+        ));
+        lines.push(Line::from(spans));
+    }
+    Text::from(lines)
 }
 
 pub fn render_ui(
     terminal: &mut Terminal<CrosstermBackend<std::io::Stderr>>,
     editor_state: &mut EditorMode,
-    state: &State,
+    state: &mut State,
 ) -> Result<()> {
     terminal.draw(|frame| {
-        let layout = layout_layer(frame);
-
-        let title = Paragraph::new(editor_state.get_file_name().unwrap_or("No file open"))
-            .block(Block::default().borders(Borders::BOTTOM))
-            .alignment(ratatui::prelude::Alignment::Center)
-            .bg(Color::LightRed)
-            .fg(Color::Magenta);
-
-        let mut my_str = state.content.clone();
-        my_str.insert(state.cursor.location, '|');
-        let main_content = Paragraph::new(my_str);
-        let footer = Paragraph::new(editor_state.display_mode()).block(
-            Block::default()
-                .borders(Borders::TOP)
-                .bg(match editor_state {
-                    EditorMode::Command(_) => Color::Blue,
-                    EditorMode::Edit(_) => Color::LightGreen,
-                    EditorMode::Idle(_) => Color::Cyan,
-                })
-                .fg(match editor_state {
-                    EditorMode::Command(_) => Color::White,
-                    EditorMode::Edit(_) => Color::Green,
-                    EditorMode::Idle(_) => Color::Blue,
-                }),
-        );
-        frame.render_widget(title, layout[0]);
-        frame.render_widget(main_content, layout[1]);
-        frame.render_widget(footer, layout[2]);
+        let areas = layout_layer(frame, state);
+
+        let (sign_width, number_width) = gutter_widths(state);
+        let gutter_width = sign_width + number_width;
+        let text_area = Rect {
+            x: areas.text.x + gutter_width.min(areas.text.width),
+            width: areas.text.width.saturating_sub(gutter_width),
+            ..areas.text
+        };
+        state.viewport.height = text_area.height as usize;
+        state.viewport.width = text_area.width as usize;
+        state.viewport.origin = (text_area.x, text_area.y);
+        if state.large.is_some() {
+            render_large(frame, &areas, state, editor_state);
+            return;
+        }
+        if state.options.binary {
+            render_hex(frame, &areas, state, editor_state);
+            return;
+        }
+        let (line, line_count) = (state.cursor_line(), state.line_count());
+        state
+            .viewport
+            .follow(line, line_count, state.options.scrolloff);
+        wrap::follow_rows(state);
+        let cursor_rows = wrap::line_rows(state, line);
+        let cursor_row = cursor_rows[wrap::row_of(&cursor_rows, state.cursor.location)];
+        let column = wrap::column_of(state, &cursor_row, state.cursor.location);
+        if state.options.wrap {
+            state.viewport.left = 0;
+        } else {
+            state.viewport.follow_column(column);
+        }
+        // only the rows in the viewport are drawn
+        let rows = wrap::visible_rows(state);
+        state.viewport.shown = wrap::shown_lines(state, &rows);
+
+        let selection = editor_state.selection(state);
+        let main_content = Paragraph::new(text_rows(state, &rows, selection));
+        if gutter_width > 0 {
+            let gutter_area = Rect {
+                width: gutter_width.min(areas.text.width),
+                ..areas.text
+            };
+            frame.render_widget(
+                Paragraph::new(gutter(state, &rows, sign_width, number_width)),
+                gutter_area,
+            );
+        }
+        frame.render_widget(main_content, text_area);
+
+        render_bars(frame, &areas, state, editor_state);
+
+        if let Some(column) = command_line_column(editor_state) {
+            frame.set_cursor(areas.command.x + column as u16, areas.command.y);
+        } else {
+            let row = rows
+                .iter()
+                .position(|row| *row == cursor_row)
+                .unwrap_or_default();
+            let column =
+                (column - state.viewport.left).min(text_area.width.saturating_sub(1) as usize);
+            frame.set_cursor(text_area.x + column as u16, text_area.y + row as u16);
+        }
     })?;
+    execute!(terminal.backend_mut(), cursor_style(editor_state))?;
     Ok(())
 }
+
+/// the lines around the text: tab line, winbar, status line and command line
+fn render_bars(frame: &mut Frame, areas: &Areas, state: &State, editor_state: &EditorMode) {
+    let file_name = state.file_name().unwrap_or("[No Name]");
+    if let Some(tabline) = areas.tabline {
+        let modified = if state.is_modified() { " [+]" } else { "" };
+        let tab = Paragraph::new(format!(" {file_name}{modified} ")).bg(Color::DarkGray);
+        frame.render_widget(tab, tabline);
+    }
+    if let Some(winbar) = areas.winbar {
+        let bar = Paragraph::new(state.options.winbar.as_str()).bold();
+        frame.render_widget(bar, winbar);
+    }
+    // the items of `statusline` need the text, which a large file does not have
+    let status = match &state.large {
+        Some(large) => Line::from(format!(" {file_name} {}", large.status())),
+        None => statusline::render(
+            &state.options.statusline,
+            state,
+            editor_state,
+            areas.status.width as usize,
+        ),
+    };
+    frame.render_widget(
+        Paragraph::new(status).style(statusline::base_style()),
+        areas.status,
+    );
+    frame.render_widget(Paragraph::new(editor_state.display_mode()), areas.command);
+}
+
+/// the hex view of a binary buffer, taking the whole width without a gutter
+fn render_hex(frame: &mut Frame, areas: &Areas, state: &mut State, editor_state: &EditorMode) {
+    state.viewport.width = areas.text.width as usize;
+    state.viewport.origin = (areas.text.x, areas.text.y);
+    let (text, (column, row)) = hex::view(state);
+    frame.render_widget(Paragraph::new(text), areas.text);
+    render_bars(frame, areas, state, editor_state);
+    if let Some(column) = command_line_column(editor_state) {
+        frame.set_cursor(areas.command.x + column as u16, areas.command.y);
+    } else {
+        let column = column.min(areas.text.width.saturating_sub(1) as usize);
+        frame.set_cursor(areas.text.x + column as u16, areas.text.y + row as u16);
+    }
+}
+
+/// a large file without a gutter, read from its memory map
+fn render_large(frame: &mut Frame, areas: &Areas, state: &mut State, editor_state: &EditorMode) {
+    state.viewport.width = areas.text.width as usize;
+    state.viewport.origin = (areas.text.x, areas.text.y);
+    let (text, (column, row)) = large::view(state);
+    frame.render_widget(Paragraph::new(text), areas.text);
+    render_bars(frame, areas, state, editor_state);
+    if let Some(column) = command_line_column(editor_state) {
+        frame.set_cursor(areas.command.x + column as u16, areas.command.y);
+    } else {
+        let column = column.min(areas.text.width.saturating_sub(1) as usize);
+        frame.set_cursor(areas.text.x + column as u16, areas.text.y + row as u16);
+    }
+}
+
+/// column of the cursor after the text typed on the command line, behind
+/// the `=> ` or the prompt for a key
+fn command_line_column(editor_state: &EditorMode) -> Option<usize> {
+    match editor_state {
+        EditorMode::Command(_) | EditorMode::Key(_) => {
+            Some(display_width(&editor_state.display_mode()))
+        }
+        _ => None,
+    }
+}
+
+/// columns taken on screen, wide characters use two
+pub fn display_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// block in idle mode, bar while inserting and underline while replacing
+fn cursor_style(editor_state: &EditorMode) -> SetCursorStyle {
+    match editor_state {
+        EditorMode::Edit(_) | EditorMode::Command(_) | EditorMode::Key(_) => {
+            SetCursorStyle::SteadyBar
+        }
+        EditorMode::Replace(_) => SetCursorStyle::SteadyUnderScore,
+        EditorMode::Idle(_) | EditorMode::Visual(_) => SetCursorStyle::SteadyBlock,
+    }
+}
@@ -0,0 +1,109 @@
+/// lines of the buffer shown in the text area
+#[derive(Default)]
+pub struct Viewport {
+    /// first visible line
+    pub top: usize,
+    /// number of rows that fit, updated on every render
+    pub height: usize,
+    /// columns of the text area, updated on every render
+    pub width: usize,
+    /// first visible column while lines are not wrapped
+    pub left: usize,
+    /// screen column and row of the top left corner of the text area
+    pub origin: (u16, u16),
+    /// lines shown completely, fewer than `height` when long lines wrap
+    pub shown: usize,
+}
+
+impl Viewport {
+    fn rows(&self) -> usize {
+        self.height.max(1)
+    }
+
+    /// lines that fit below `top`, every line takes one row until a render
+    /// tells how lines wrap
+    fn lines(&self) -> usize {
+        if self.shown > 0 {
+            self.shown.min(self.rows())
+        } else {
+            self.rows()
+        }
+    }
+
+    /// scrolloff reduced so that the cursor always has a row to stay on
+    fn margin(&self, scrolloff: usize) -> usize {
+        scrolloff.min((self.rows() - 1) / 2)
+    }
+
+    fn max_top(&self, line_count: usize) -> usize {
+        line_count.saturating_sub(1)
+    }
+
+    /// scrolls just enough to show `line` with `scrolloff` lines around it
+    pub fn follow(&mut self, line: usize, line_count: usize, scrolloff: usize) {
+        let margin = self.margin(scrolloff);
+        if line < self.top + margin {
+            self.top = line.saturating_sub(margin);
+        } else if line + margin >= self.top + self.rows() {
+            self.top = (line + margin + 1).saturating_sub(self.rows());
+        }
+        self.top = self.top.min(self.max_top(line_count));
+    }
+
+    /// scrolls by `lines` without moving past the first or last line
+    pub fn scroll(&mut self, lines: isize, line_count: usize) {
+        self.top = self
+            .top
+            .saturating_add_signed(lines)
+            .min(self.max_top(line_count));
+    }
+
+    /// the line closest to `line` that is not hidden by scrolling or `scrolloff`
+    pub fn clamp_line(&self, line: usize, line_count: usize, scrolloff: usize) -> usize {
+        let margin = self.margin(scrolloff);
+        let last_line = line_count.saturating_sub(1);
+        let first = if self.top == 0 { 0 } else { self.top + margin }.min(last_line);
+        let bottom = self.top + self.lines() - 1;
+        // a wrapped line taller than the window leaves no room for the margin
+        let last = if bottom >= last_line {
+            last_line
+        } else {
+            bottom.saturating_sub(margin)
+        };
+        line.clamp(first, last.max(first))
+    }
+
+    /// `zt`, `zz` and `zb` put `line` at the top, center or bottom
+    pub fn align(&mut self, line: usize, position: char, scrolloff: usize) {
+        let margin = self.margin(scrolloff);
+        self.top = match position {
+            't' => line.saturating_sub(margin),
+            'z' => line.saturating_sub(self.rows() / 2),
+            'b' => (line + margin + 1).saturating_sub(self.rows()),
+            _ => self.top,
+        };
+    }
+
+    /// last line that fits below `top`
+    pub fn bottom(&self, line_count: usize) -> usize {
+        (self.top + self.lines()).min(line_count).saturating_sub(1)
+    }
+
+    /// forgets how lines wrapped after the terminal was resized, the next
+    /// render fits the view to the new size
+    pub fn reflow(&mut self, columns: u16, rows: u16) {
+        self.shown = 0;
+        self.width = self.width.min(columns as usize);
+        self.height = self.height.min(rows as usize);
+    }
+
+    /// scrolls sideways just enough to show `column`
+    pub fn follow_column(&mut self, column: usize) {
+        let columns = self.width.max(1);
+        if column < self.left {
+            self.left = column;
+        } else if column >= self.left + columns {
+            self.left = column + 1 - columns;
+        }
+    }
+}
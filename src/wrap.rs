@@ -0,0 +1,218 @@
+use unicode_width::UnicodeWidthChar;
+
+use crate::{editor_state::State, ui::display_width};
+
+/// characters after which `linebreak` wraps a line, like vim's `breakat`
+const BREAKAT: &str = " \t!@*-+;:,./?";
+
+/// the part of a buffer line shown on one screen row
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisplayRow {
+    pub line: usize,
+    /// byte offsets of the shown text
+    pub start: usize,
+    pub end: usize,
+    /// the row continues a wrapped line
+    pub continued: bool,
+    /// columns taken by `showbreak` and `breakindent` before the text
+    pub prefix: usize,
+}
+
+fn char_width(ch: char) -> usize {
+    ch.width().unwrap_or(0)
+}
+
+/// splits `line` into the rows it takes on screen, one row while `nowrap` is set
+pub fn line_rows(state: &State, line: usize) -> Vec<DisplayRow> {
+    let options = &state.options;
+    let (line_start, line_end) = (state.line_start(line), state.line_end(line));
+    let width = state.viewport.width;
+    let whole = DisplayRow {
+        line,
+        start: line_start,
+        end: line_end,
+        continued: false,
+        prefix: 0,
+    };
+    if !options.wrap || width == 0 {
+        return vec![whole];
+    }
+    let text = &state.content[line_start..line_end];
+    let indent = if options.breakindent {
+        let blanks = text.len() - text.trim_start().len();
+        display_width(&text[..blanks])
+    } else {
+        0
+    };
+    // like vim the prefix is dropped when it leaves no room for the text
+    let continued_prefix = display_width(&options.showbreak) + indent;
+    let continued_prefix = if continued_prefix < width {
+        continued_prefix
+    } else {
+        0
+    };
+
+    let mut rows = Vec::new();
+    let mut start = 0;
+    loop {
+        let continued = !rows.is_empty();
+        let prefix = if continued { continued_prefix } else { 0 };
+        let room = width - prefix;
+        let mut columns = 0;
+        let mut end = start;
+        for (index, ch) in text[start..].char_indices() {
+            let ch_width = char_width(ch);
+            if columns + ch_width > room && end > start {
+                break;
+            }
+            columns += ch_width;
+            end = start + index + ch.len_utf8();
+        }
+        if end < text.len() && options.linebreak {
+            if let Some(position) = text[start..end].rfind(|ch| BREAKAT.contains(ch)) {
+                let cut = start + position + 1;
+                if cut > start {
+                    end = cut;
+                }
+            }
+        }
+        rows.push(DisplayRow {
+            start: line_start + start,
+            end: line_start + end,
+            continued,
+            prefix,
+            ..whole
+        });
+        if end >= text.len() {
+            return rows;
+        }
+        start = end;
+    }
+}
+
+/// index of the row in `rows` that shows `location`
+pub fn row_of(rows: &[DisplayRow], location: usize) -> usize {
+    rows.iter()
+        .rposition(|row| row.start <= location)
+        .unwrap_or(0)
+}
+
+/// screen column of `location` on its row, before horizontal scrolling
+pub fn column_of(state: &State, row: &DisplayRow, location: usize) -> usize {
+    row.prefix + display_width(&state.content[row.start..location.clamp(row.start, row.end)])
+}
+
+/// offset of the character on `row` at screen `column`, the last one when
+/// the row is shorter
+pub fn location_at(state: &State, row: &DisplayRow, column: usize, last_row: bool) -> usize {
+    let text = &state.content[row.start..row.end];
+    let mut columns = row.prefix;
+    let mut last = row.start;
+    for (index, ch) in text.char_indices() {
+        columns += char_width(ch);
+        last = row.start + index;
+        if columns > column {
+            return last;
+        }
+    }
+    // the end of a line is a valid position, the end of a wrapped row is the next row
+    if last_row {
+        row.end
+    } else {
+        last
+    }
+}
+
+/// byte range of `text` between screen columns `from` and `to`, a wide
+/// character cut by `from` is left out
+pub fn columns(text: &str, from: usize, to: usize) -> (usize, usize) {
+    let mut column = 0;
+    let mut start = text.len();
+    let mut end = text.len();
+    for (index, ch) in text.char_indices() {
+        if column >= from && start == text.len() {
+            start = index;
+        }
+        column += char_width(ch);
+        if column > to {
+            end = index;
+            break;
+        }
+    }
+    (start.min(end), end)
+}
+
+/// the rows filling the text area from the top of the viewport, a cursor line
+/// that is too long for the screen starts at the row with the cursor
+pub fn visible_rows(state: &State) -> Vec<DisplayRow> {
+    let height = state.viewport.height.max(1);
+    let line_count = state.line_count();
+    let cursor_line = state.cursor_line();
+    let mut rows = Vec::new();
+    for line in state.viewport.top..line_count {
+        let mut line_rows = line_rows(state, line);
+        if line == cursor_line && line == state.viewport.top {
+            let row = row_of(&line_rows, state.cursor.location);
+            line_rows.drain(..(row + 1).saturating_sub(height));
+        }
+        rows.extend(line_rows);
+        if rows.len() >= height {
+            break;
+        }
+    }
+    rows.truncate(height);
+    rows
+}
+
+/// offset shown at a screen position, clicks left of the text go to the
+/// start of the row and clicks below the text to the last row
+pub fn location_on_screen(state: &State, column: u16, row: u16) -> usize {
+    let (left, top) = state.viewport.origin;
+    let rows = visible_rows(state);
+    let index = (row.saturating_sub(top) as usize).min(rows.len().saturating_sub(1));
+    let Some(display_row) = rows.get(index) else {
+        return 0;
+    };
+    let column = column.saturating_sub(left) as usize + state.viewport.left;
+    let last_row = display_row.end == state.line_end(display_row.line);
+    location_at(state, display_row, column, last_row)
+}
+
+/// moves the top line down until the cursor line and `scrolloff` lines after
+/// it fit, which `Viewport::follow` can not know when lines wrap
+pub fn follow_rows(state: &mut State) {
+    if !state.options.wrap {
+        return;
+    }
+    let height = state.viewport.height.max(1);
+    let line_count = state.line_count();
+    let cursor_line = state.cursor_line();
+    let margin = state.options.scrolloff.min((height - 1) / 2);
+    let last = (cursor_line + margin).min(line_count - 1);
+    let counts: Vec<usize> = (state.viewport.top..=last)
+        .map(|line| line_rows(state, line).len())
+        .collect();
+    let mut used: usize = counts.iter().sum();
+    for count in counts {
+        if used <= height || state.viewport.top >= cursor_line {
+            break;
+        }
+        used -= count;
+        state.viewport.top += 1;
+    }
+}
+
+/// number of lines from the top that are shown completely, like the height
+/// when every line takes one row
+pub fn shown_lines(state: &State, rows: &[DisplayRow]) -> usize {
+    let height = state.viewport.height.max(1);
+    let Some(last) = rows.last() else {
+        return height;
+    };
+    if rows.len() < height {
+        return height;
+    }
+    let complete = last.end == state.line_end(last.line);
+    let lines = last.line - state.viewport.top + usize::from(complete);
+    lines.max(1)
+}
@@ -0,0 +1,139 @@
+use anyhow::Result;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Error, ErrorKind, Write},
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use crate::options::Options;
+
+/// writes `bytes` to `path` so that a crash or a full disk leaves either the
+/// old or the new content
+///
+/// The text goes to a temporary file in the same directory, which is synced
+/// and renamed over the file keeping its mode and owner. A symlink is followed
+/// and its target is replaced, so the link stays. A file with several hard
+/// links is overwritten in place instead, because a rename would split it from
+/// its other names, with a backup copy kept until the write succeeded.
+pub fn write_file(path: &Path, bytes: &[u8], options: &Options) -> Result<()> {
+    let target = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => fs::canonicalize(path)?,
+        _ => path.to_path_buf(),
+    };
+    let existing = fs::metadata(&target).ok();
+    let backup = match &existing {
+        Some(_) if options.backup || options.writebackup => Some(make_backup(&target, options)?),
+        _ => None,
+    };
+
+    match &existing {
+        Some(meta) if meta.nlink() > 1 => write_in_place(&target, bytes)?,
+        Some(meta) => write_and_rename(
+            &target,
+            bytes,
+            Some(meta.mode()),
+            Some((meta.uid(), meta.gid())),
+        )?,
+        None => write_and_rename(&target, bytes, None, None)?,
+    }
+
+    // the copy made only for the write is not needed anymore
+    if let Some(backup) = backup {
+        if !options.backup {
+            fs::remove_file(backup)?;
+        }
+    }
+    Ok(())
+}
+
+/// replaces `path` like [`write_file`] but readable only by the user, for
+/// files like swap files that hold text of other files
+pub fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    write_and_rename(path, bytes, Some(0o600), None)
+}
+
+fn write_and_rename(
+    target: &Path,
+    bytes: &[u8],
+    mode: Option<u32>,
+    owner: Option<(u32, u32)>,
+) -> Result<()> {
+    let dir = parent_dir(target);
+    let name = file_name(target)?;
+    let temp = dir.join(format!(".{name}.{}.tmp", std::process::id()));
+    let result = (|| -> Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        file.write_all(bytes)?;
+        if let Some(mode) = mode {
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+        if let Some((uid, gid)) = owner {
+            // only root can give the file away, keeping the group is often allowed
+            let _ = std::os::unix::fs::fchown(&file, Some(uid), Some(gid));
+        }
+        file.sync_all()?;
+        fs::rename(&temp, target)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+    // the rename is only durable once the directory is synced
+    if let Ok(dir) = File::open(&dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+fn write_in_place(target: &Path, bytes: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new().write(true).truncate(true).open(target)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// copies the file to the first usable directory of `backupdir`, `.` being
+/// the directory of the file, with `backupext` appended to the name
+fn make_backup(target: &Path, options: &Options) -> Result<PathBuf> {
+    let name = file_name(target)?;
+    for dir in options.backupdir.split(',').filter(|dir| !dir.is_empty()) {
+        let dir = if dir == "." {
+            parent_dir(target)
+        } else {
+            PathBuf::from(dir)
+        };
+        if !dir.is_dir() {
+            continue;
+        }
+        let backup = dir.join(format!("{name}{}", options.backupext));
+        fs::copy(target, &backup)?;
+        return Ok(backup);
+    }
+    Err(anyhow::Error::from(Error::new(
+        ErrorKind::NotFound,
+        "E510: Can't make backup file",
+    )))
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| {
+            anyhow::Error::from(Error::new(
+                ErrorKind::InvalidInput,
+                format!("not a file name: {}", path.display()),
+            ))
+        })
+}